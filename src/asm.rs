@@ -0,0 +1,217 @@
+//! Export of the analyzed ROM as RGBDS assembly.
+//!
+//! Every byte of the ROM is written out, so assembling the output with RGBDS gives back the
+//! original ROM. Each 16KiB bank gets its own section.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::disassembler::{AddressRepr, Disassembler};
+use crate::gb::{
+    self, Argument, GBInstruction, GameBoy, Reg8, ResolvedAddress, UnmappedAddress, BANK_SIZE,
+};
+
+pub fn export(path: &Path, disassembler: &Disassembler<GameBoy>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    // Labels in the middle of an instruction or data line can't be emitted, so they can't
+    // be referenced either
+    let emitted_labels: HashSet<usize> = disassembler
+        .iter_from(0)
+        .filter_map(|repr| match repr {
+            AddressRepr::Label { address, .. } => Some(address),
+            _ => None,
+        })
+        .collect();
+    let exporter = Exporter {
+        disassembler,
+        emitted_labels,
+    };
+
+    let mut next_section = 0;
+    for repr in disassembler.iter_from(0) {
+        let address = match repr {
+            AddressRepr::Label { address, .. }
+            | AddressRepr::Unknown { address, .. }
+            | AddressRepr::Data { address, .. }
+            | AddressRepr::Dump { address, .. }
+            | AddressRepr::Padding { address, .. }
+            | AddressRepr::Text { address, .. }
+            | AddressRepr::Pointer { address, .. }
+            | AddressRepr::Code { address, .. }
+            | AddressRepr::Undecoded { address, .. } => address,
+        };
+        if address >= next_section {
+            let bank = address / BANK_SIZE;
+            if bank == 0 {
+                writeln!(file, "SECTION \"ROM0\", ROM0[$0000]")?;
+            } else {
+                writeln!(file)?;
+                writeln!(
+                    file,
+                    "SECTION \"ROM Bank ${:02x}\", ROMX[${:04x}], BANK[${:02x}]",
+                    bank,
+                    BANK_SIZE + address % BANK_SIZE,
+                    bank
+                )?;
+            }
+            next_section = (bank + 1) * BANK_SIZE;
+        }
+
+        let line = match repr {
+            AddressRepr::Label { name, .. } => {
+                writeln!(file, "{}:", name)?;
+                continue;
+            }
+            AddressRepr::Unknown { byte, .. } => format!("db ${:02x} ; unknown", byte),
+            AddressRepr::Undecoded { byte, .. } => format!("db ${:02x} ; undecoded", byte),
+            AddressRepr::Data { bytes, .. } | AddressRepr::Dump { bytes, .. } => {
+                format!("db {}", format_bytes(bytes))
+            }
+            AddressRepr::Padding { byte, length, .. } => format!("ds {}, ${:02x}", length, byte),
+            AddressRepr::Text { bytes, .. } => format!("db {}", format_string(bytes)),
+            AddressRepr::Pointer { address, bytes } => match *bytes {
+                [low, high] => {
+                    let pointer = UnmappedAddress(u16::from_le_bytes([low, high]));
+                    let argument = Argument::Address(pointer);
+                    format!("dw {}", exporter.format_argument(address, true, &argument))
+                }
+                _ => format!("db {}", format_bytes(bytes)),
+            },
+            AddressRepr::Code {
+                address,
+                instruction,
+                ..
+            } => exporter.format_instruction(address, &instruction),
+        };
+        match disassembler.comment(address) {
+            Some(comment) => writeln!(file, "\t{} ; {}", line, comment)?,
+            None => writeln!(file, "\t{}", line)?,
+        }
+    }
+
+    file.flush()
+}
+
+struct Exporter<'a> {
+    disassembler: &'a Disassembler<GameBoy>,
+    emitted_labels: HashSet<usize>,
+}
+
+impl Exporter<'_> {
+    fn format_instruction(&self, read_at: usize, instruction: &GBInstruction) -> String {
+        match instruction {
+            GBInstruction::Illegal(opcode) => return format!("db ${:02x} ; illegal", opcode),
+            // Shown as `JP (HL)` in the disassembly, but RGBDS only knows the `JP HL` form
+            GBInstruction::JPHL => return "JP HL".to_string(),
+            _ => {}
+        }
+        let is_jump = instruction.jump_address().is_some();
+        let arguments: Vec<String> = instruction
+            .first_argument()
+            .into_iter()
+            .chain(instruction.second_argument())
+            .map(|argument| self.format_argument(read_at, is_jump, &argument))
+            .collect();
+
+        // Conditions are part of the name, e.g. `JR NZ`, RGBDS wants them separated from the
+        // target by a comma
+        let mut operands: Vec<&str> = Vec::new();
+        let mut name_parts = instruction.name().splitn(2, ' ');
+        let mnemonic = name_parts.next().unwrap_or_default();
+        operands.extend(name_parts);
+        operands.extend(arguments.iter().map(String::as_str));
+
+        if operands.is_empty() {
+            mnemonic.to_string()
+        } else {
+            format!("{} {}", mnemonic, operands.join(", "))
+        }
+    }
+
+    /// Formats `argument` in RGBDS syntax. Addresses are memory accesses unless `is_jump` is set,
+    /// which is also used for pointers.
+    fn format_argument(&self, read_at: usize, is_jump: bool, argument: &Argument) -> String {
+        match *argument {
+            Argument::Imm8(value) => format!("${:02x}", value),
+            Argument::Imm16(value) => format!("${:04x}", value),
+            Argument::Rel8(value) => {
+                // The offset is relative to the end of the 2-byte JR instruction
+                let target = read_at.wrapping_add(2).wrapping_add(value as usize);
+                if self.emitted_labels.contains(&target) {
+                    self.label(target).to_string()
+                } else {
+                    format!("@{:+}", value as isize + 2)
+                }
+            }
+            Argument::Reg8(Reg8::IndirectHL) => "[HL]".to_string(),
+            Argument::Reg8(register) => format!("{}", register),
+            Argument::Reg16(register) => format!("{}", register),
+            Argument::Address(unmapped_address) => {
+                let target = match gb::resolve_physical_address(
+                    self.disassembler,
+                    read_at,
+                    unmapped_address,
+                ) {
+                    ResolvedAddress::Physical(address)
+                        if self.emitted_labels.contains(&address) =>
+                    {
+                        self.label(address).to_string()
+                    }
+                    _ => format!("${:04x}", unmapped_address.0),
+                };
+                if is_jump {
+                    target
+                } else {
+                    format!("[{}]", target)
+                }
+            }
+            Argument::IndirectReg16(register) => format!("[{}]", register),
+            Argument::IndirectHLinc => "[HL+]".to_string(),
+            Argument::IndirectHLdec => "[HL-]".to_string(),
+            Argument::IndirectC => "[$ff00+C]".to_string(),
+            Argument::ResetVector(reset_vector) => format!("${:02x}", reset_vector.address().0),
+            Argument::SignedImm8(value) => format!("{}", value),
+            Argument::SPRel8(value) => format!("SP{:+}", value),
+            Argument::BitIndex(bit) => format!("{}", bit),
+        }
+    }
+
+    fn label(&self, address: usize) -> &str {
+        self.disassembler.label(address).unwrap_or_default()
+    }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("${:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Like `format_text`, but also escapes the braces RGBDS uses for symbol interpolation
+fn format_string(bytes: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    for &byte in bytes {
+        if (0x20..=0x7e).contains(&byte) {
+            if matches!(byte, b'"' | b'\\' | b'{' | b'}') {
+                literal.push('\\');
+            }
+            literal.push(byte as char);
+        } else {
+            if !literal.is_empty() {
+                parts.push(format!("\"{}\"", literal));
+                literal.clear();
+            }
+            parts.push(format!("${:02x}", byte));
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(format!("\"{}\"", literal));
+    }
+    parts.join(", ")
+}
@@ -1,120 +1,1157 @@
-use std::marker::PhantomData;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
-pub struct DisassemblerState<'a> {
-    rom: &'a [u8],
+use serde::Serialize;
+
+/// Maximum number of data bytes shown on a single line
+const DATA_LINE_LENGTH: usize = 8;
+
+/// Number of bytes on a row of the hex dump layout
+const DUMP_LINE_LENGTH: usize = 16;
+
+/// Byte ending a text string
+const TEXT_TERMINATOR: u8 = 0x00;
+
+/// Size of the entries of pointer tables
+pub const POINTER_SIZE: usize = 2;
+
+/// Bytes ROMs are usually padded with
+const PADDING_BYTES: [u8; 2] = [0x00, 0xff];
+
+/// Minimum length of the runs of padding collapsed into a single line
+const PADDING_RUN_LENGTH: usize = 32;
+
+/// Number of consecutive traps from which they are taken for padding rather than code
+const TRAP_RUN_LENGTH: usize = 3;
+
+pub struct DisassemblerState {
+    rom: Vec<u8>,
     byte_type: Vec<ByteType>,
+    labels: HashMap<usize, String>,
+    /// The addresses of `labels` in order, to step through them
+    label_addresses: BTreeSet<usize>,
+    comments: HashMap<usize, String>,
+    /// Addresses saved by the user under a single letter
+    bookmarks: HashMap<char, usize>,
+    /// The ROM bank that banked addresses refer to, for the instructions at the given
+    /// locations
+    banks: HashMap<usize, usize>,
+    /// The ROM bank for all the instructions of a span, by start of the span, holding its end
+    /// and the bank. Spans don't overlap, and `banks` takes precedence over them.
+    bank_ranges: BTreeMap<usize, (usize, usize)>,
+    /// The addresses of the code branching to each address
+    xrefs: HashMap<usize, Vec<usize>>,
+    /// The addresses of the code loading from or storing to each address
+    data_xrefs: HashMap<usize, Vec<usize>>,
+    /// The original value of every byte of `rom` that was patched
+    patches: BTreeMap<usize, u8>,
 }
 
-impl<'a> DisassemblerState<'a> {
-    pub fn new(rom: &[u8]) -> DisassemblerState {
+impl DisassemblerState {
+    pub fn new(rom: Vec<u8>) -> DisassemblerState {
         let size = rom.len();
         DisassemblerState {
             rom,
             byte_type: vec![ByteType::Unknown; size],
+            labels: HashMap::new(),
+            label_addresses: BTreeSet::new(),
+            comments: HashMap::new(),
+            bookmarks: HashMap::new(),
+            banks: HashMap::new(),
+            bank_ranges: BTreeMap::new(),
+            xrefs: HashMap::new(),
+            data_xrefs: HashMap::new(),
+            patches: BTreeMap::new(),
         }
     }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn bank(&self, location: usize) -> Option<usize> {
+        self.banks.get(&location).copied().or_else(|| {
+            self.bank_ranges
+                .range(..=location)
+                .next_back()
+                .filter(|&(_, &(end, _))| location < end)
+                .map(|(_, &(_, bank))| bank)
+        })
+    }
+}
+
+/// A single modification of the state, holding the value to restore
+enum Edit {
+    ByteType(usize, ByteType),
+    Label(usize, Option<String>),
+    Comment(usize, Option<String>),
+    Bank(usize, Option<usize>),
+    BankRange(usize, Option<(usize, usize)>),
+    Byte(usize, u8),
 }
 
-pub struct Disassembler<'a, Architecture> {
-    state: DisassemblerState<'a>,
+pub struct Disassembler<Arch: Architecture> {
+    state: DisassemblerState,
+    /// The instruction decoded at every address of the ROM, so that drawing and analysis
+    /// don't decode the same bytes over and over
+    instructions: Vec<Option<Arch::Instruction>>,
 
-    phantom: PhantomData<Architecture>,
+    /// Edits made since the last call to `commit_edits`
+    pending_edits: Vec<Edit>,
+    /// Groups of edits that can be undone, most recent last
+    undo_stack: Vec<Vec<Edit>>,
+    /// Groups of undone edits that can be redone, most recent last
+    redo_stack: Vec<Vec<Edit>>,
+
+    /// The start and end of the padding runs shown byte by byte instead of collapsed
+    expanded_padding: BTreeMap<usize, usize>,
+    /// The bank banked addresses are shown in when neither the banks nor the bank ranges
+    /// give it, set by the user while exploring a bank
+    current_bank: Option<usize>,
 }
 
-// TODO events
-// TODO display
-impl<'a, Arch: Architecture> Disassembler<'a, Arch> {
-    pub fn new(rom: &[u8]) -> Disassembler<Arch> {
-        let size = rom.len();
+impl<Arch: Architecture> Disassembler<Arch> {
+    pub fn new(rom: Vec<u8>) -> Disassembler<Arch> {
+        let instructions = (0..rom.len())
+            .map(|address| Arch::disassemble(&rom[address..]))
+            .collect();
         Disassembler {
             state: DisassemblerState::new(rom),
+            instructions,
+
+            pending_edits: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+
+            expanded_padding: BTreeMap::new(),
+            current_bank: None,
+        }
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.state.rom
+    }
+
+    /// The ROM as it was loaded, without the patches
+    pub fn original_rom(&self) -> Vec<u8> {
+        let mut rom = self.state.rom.clone();
+        for (&address, &original) in &self.state.patches {
+            rom[address] = original;
+        }
+        rom
+    }
+
+    /// Overwrites the byte at `address`, the original value is kept so that the patch can be
+    /// reverted
+    pub fn patch_byte(&mut self, address: usize, value: u8) {
+        self.write_byte(address, value);
+    }
 
-            phantom: PhantomData,
+    /// Restores the original value of the byte at `address`, returns false if it wasn't
+    /// patched
+    pub fn revert_patch(&mut self, address: usize) -> bool {
+        match self.state.patches.get(&address) {
+            Some(&original) => {
+                self.write_byte(address, original);
+                true
+            }
+            None => false,
         }
     }
 
+    pub fn is_patched(&self, address: usize) -> bool {
+        self.state.patches.contains_key(&address)
+    }
+
+    /// The patched addresses along with their original value, in order
+    pub fn patches(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.state
+            .patches
+            .iter()
+            .map(|(&address, &original)| (address, original))
+    }
+
+    pub fn byte_type(&self, address: usize) -> ByteType {
+        self.state.byte_type[address]
+    }
+
+    pub fn set_byte_type(&mut self, address: usize, byte_type: ByteType) {
+        self.write_byte_type(address, byte_type);
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.state
+            .labels
+            .iter()
+            .map(|(&address, name)| (address, name.as_str()))
+    }
+
+    pub fn banks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.state
+            .banks
+            .iter()
+            .map(|(&location, &bank)| (location, bank))
+    }
+
+    pub fn label(&self, address: usize) -> Option<&str> {
+        self.state.labels.get(&address).map(String::as_str)
+    }
+
+    /// Sets the label at `address`, an empty name removes it
+    pub fn set_label(&mut self, address: usize, name: String) {
+        let name = if name.is_empty() { None } else { Some(name) };
+        self.write_label(address, name);
+    }
+
+    /// Whether the label at `address` is one `mark_code` made up for a branch target
+    pub fn is_generated_label(&self, address: usize) -> bool {
+        self.state.labels.get(&address).is_some_and(|name| {
+            *name == format!("LOC_{:06X}", address) || *name == format!("SUB_{:06X}", address)
+        })
+    }
+
+    pub fn comments(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.state
+            .comments
+            .iter()
+            .map(|(&address, comment)| (address, comment.as_str()))
+    }
+
+    pub fn comment(&self, address: usize) -> Option<&str> {
+        self.state.comments.get(&address).map(String::as_str)
+    }
+
+    /// Sets the comment of the item containing `address`, an empty comment removes it
+    pub fn set_comment(&mut self, address: usize, comment: String) {
+        let address = self.align_address_to_valid_location(address);
+        let comment = if comment.is_empty() {
+            None
+        } else {
+            Some(comment)
+        };
+        self.write_comment(address, comment);
+    }
+
+    pub fn bookmarks(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        self.state
+            .bookmarks
+            .iter()
+            .map(|(&name, &address)| (name, address))
+    }
+
+    pub fn bookmark(&self, name: char) -> Option<usize> {
+        self.state.bookmarks.get(&name).copied()
+    }
+
+    pub fn set_bookmark(&mut self, name: char, address: usize) {
+        self.state.bookmarks.insert(name, address);
+    }
+
+    pub fn bank(&self, location: usize) -> Option<usize> {
+        self.state.bank(location)
+    }
+
+    pub fn current_bank(&self) -> Option<usize> {
+        self.current_bank
+    }
+
+    /// Sets the bank assumed for the banked addresses of the code whose bank isn't known. It
+    /// is a view setting, it isn't saved nor undone, and the analysis doesn't use it.
+    pub fn set_current_bank(&mut self, bank: Option<usize>) {
+        self.current_bank = bank;
+    }
+
+    pub fn set_bank(&mut self, location: usize, bank: usize) {
+        self.write_bank(location, Some(bank));
+    }
+
+    /// Returns the spans set with `set_bank_range`, as start, end and bank
+    pub fn bank_ranges(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.state
+            .bank_ranges
+            .iter()
+            .map(|(&start, &(end, bank))| (start, end, bank))
+    }
+
+    /// Sets the bank for all the instructions from `start` to `end`, replacing the spans it
+    /// overlaps
+    pub fn set_bank_range(&mut self, start: usize, end: usize, bank: usize) {
+        let overlapping: Vec<(usize, (usize, usize))> = self
+            .state
+            .bank_ranges
+            .range(..end)
+            .filter(|&(_, &(range_end, _))| range_end > start)
+            .map(|(&range_start, &range)| (range_start, range))
+            .collect();
+        for (range_start, (range_end, range_bank)) in overlapping {
+            self.write_bank_range(range_start, None);
+            if range_start < start {
+                self.write_bank_range(range_start, Some((start, range_bank)));
+            }
+            if range_end > end {
+                self.write_bank_range(end, Some((range_end, range_bank)));
+            }
+        }
+        self.write_bank_range(start, Some((end, bank)));
+    }
+
+    /// Returns the addresses of the instructions branching to `address`, as of the last
+    /// `update_xrefs`
+    pub fn xrefs(&self, address: usize) -> &[usize] {
+        self.state
+            .xrefs
+            .get(&address)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the addresses of the instructions loading from or storing to `address`, as of
+    /// the last `update_xrefs`
+    pub fn data_xrefs(&self, address: usize) -> &[usize] {
+        self.state
+            .data_xrefs
+            .get(&address)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns every address branched to with the instructions branching to it, as of the
+    /// last `update_xrefs`
+    pub fn all_xrefs(&self) -> impl Iterator<Item = (usize, &[usize])> {
+        self.state
+            .xrefs
+            .iter()
+            .map(|(&address, sources)| (address, sources.as_slice()))
+    }
+
+    /// Rebuilds the cross-reference indexes from the instructions currently marked as code
+    pub fn update_xrefs(&mut self) {
+        let mut xrefs: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut data_xrefs: HashMap<usize, Vec<usize>> = HashMap::new();
+        // Only the first byte of an instruction is marked as code
+        for (address, instruction) in self.instructions.iter().enumerate() {
+            if self.state.byte_type[address] != ByteType::Code {
+                continue;
+            }
+            let instruction = match instruction {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            if let Some(target) = instruction
+                .branch_address()
+                .and_then(|target| Arch::resolve_address(target, address, &self.state))
+            {
+                xrefs.entry(target).or_default().push(address);
+            }
+            if let Some(target) = instruction
+                .data_address()
+                .and_then(|target| Arch::resolve_address(target, address, &self.state))
+            {
+                data_xrefs.entry(target).or_default().push(address);
+            }
+        }
+        self.state.xrefs = xrefs;
+        self.state.data_xrefs = data_xrefs;
+    }
+
+    /// Returns where the instruction at `address` can branch to, if it is a branch whose
+    /// target can be resolved
+    pub fn branch_target(&self, address: usize) -> Option<usize> {
+        self.instruction_at(address)
+            .and_then(|instruction| instruction.branch_address())
+            .and_then(|target| Arch::resolve_address(target, address, &self.state))
+    }
+
+    pub fn instruction_at(&self, address: usize) -> Option<Arch::Instruction> {
+        self.instructions.get(address).copied().flatten()
+    }
+
     pub fn mark_data(&mut self, address: usize) {
-        self.state.byte_type[address] = ByteType::Data;
+        self.write_byte_type(address, ByteType::Data);
+    }
+
+    /// Marks the run of printable ASCII characters starting at `address` as text, including
+    /// its terminator if there is one. The run stops at the first byte that already has a type.
+    pub fn mark_text(&mut self, address: usize) {
+        let mut address = address;
+        while address < self.state.rom.len() && self.state.byte_type[address] == ByteType::Unknown {
+            let byte = self.state.rom[address];
+            if byte == TEXT_TERMINATOR {
+                self.write_byte_type(address, ByteType::Text);
+                break;
+            }
+            if !(0x20..=0x7e).contains(&byte) {
+                break;
+            }
+            self.write_byte_type(address, ByteType::Text);
+            address += 1;
+        }
+    }
+
+    /// Marks the pointer starting at `address` as part of a pointer table
+    pub fn mark_pointer(&mut self, address: usize) {
+        let end = (address + POINTER_SIZE).min(self.state.rom.len());
+        for address in address..end {
+            self.write_byte_type(address, ByteType::Pointer);
+        }
     }
 
     pub fn mark_unknown(&mut self, address: usize) {
-        self.state.byte_type[address] = ByteType::Unknown;
+        self.write_byte_type(address, ByteType::Unknown);
+    }
+
+    /// Marks `address` as code and follows the execution flow from there, marking every
+    /// reachable instruction as code and labeling the branch targets. Following stops before
+    /// instructions overlapping bytes marked as data, text or pointers, only the instruction at
+    /// `address` may replace them. Illegal opcodes and instructions cut by the end of the ROM
+    /// also stop it, their first byte is marked as data so that they stand out.
+    pub fn mark_code(&mut self, address: usize) -> CodeReport {
+        self.follow_code(&[address], Some(address), false)
     }
 
-    pub fn mark_code(&mut self, mut address: usize) {
-        let mut branches = vec![address];
-        while let Some(mut address) = branches.pop() {
-            while let Some(instruction) = Arch::disassemble(&self.state.rom[address..]) {
-                if self.state.byte_type[address] != ByteType::Code {
+    /// Like `mark_code` from all of `starts`, also going through the code that is already
+    /// marked so that the branches found since, e.g. by setting banks, are followed too.
+    /// Branches are followed breadth-first until no new code is reachable. Only the
+    /// instruction at `target`, the one the user asked for, may replace data.
+    pub fn analyze(&mut self, starts: &[usize], target: Option<usize>) -> CodeReport {
+        self.follow_code(starts, target, true)
+    }
+
+    fn follow_code(
+        &mut self,
+        starts: &[usize],
+        target: Option<usize>,
+        through_code: bool,
+    ) -> CodeReport {
+        let mut report = CodeReport::default();
+        let mut visited = HashSet::new();
+        let mut branches: VecDeque<usize> = starts.iter().copied().collect();
+        while let Some(mut address) = branches.pop_front() {
+            while address < self.state.rom.len() {
+                let is_code = self.state.byte_type[address] == ByteType::Code;
+                if (is_code && !through_code) || !visited.insert(address) {
                     break;
                 }
-                self.state.byte_type[address] = ByteType::Code;
-                if let Some(branch_address) =
-                    instruction.branch_address().and_then(|branch_address| {
+                let instruction = match self.instruction_at(address) {
+                    Some(instruction) => instruction,
+                    None if is_code => break,
+                    None => {
+                        if self.state.byte_type[address] == ByteType::Unknown {
+                            self.write_byte_type(address, ByteType::Data);
+                        }
+                        report.truncated.push(address);
+                        break;
+                    }
+                };
+                if !is_code {
+                    if Some(address) != target && self.overlaps_data(address, instruction.size()) {
+                        report.data_overlaps.push(address);
+                        break;
+                    }
+                    if instruction.is_illegal() {
+                        self.write_byte_type(address, ByteType::Data);
+                        report.illegal.push(address);
+                        break;
+                    }
+                    if self.is_trap_run(address) {
+                        report.traps.push(address);
+                        break;
+                    }
+                    self.write_byte_type(address, ByteType::Code);
+                    report.bytes += instruction.size();
+                }
+                // Branches out of the ROM can't be followed nor labelled
+                let rom_length = self.state.rom.len();
+                if let Some(branch_address) = instruction
+                    .branch_address()
+                    .and_then(|branch_address| {
                         Arch::resolve_address(branch_address, address, &self.state)
                     })
+                    .filter(|&branch_address| branch_address < rom_length)
                 {
-                    branches.push(branch_address);
+                    // Calls go to subroutines, other branches to local labels. A local label
+                    // is renamed when a call to it is found, names given by the user are kept.
+                    let local_label = format!("LOC_{:06X}", branch_address);
+                    let label = if instruction.is_call() {
+                        format!("SUB_{:06X}", branch_address)
+                    } else {
+                        local_label.clone()
+                    };
+                    match self.state.labels.get(&branch_address) {
+                        None => {
+                            self.write_label(branch_address, Some(label));
+                            report.labels += 1;
+                        }
+                        Some(existing) if *existing == local_label && label != local_label => {
+                            self.write_label(branch_address, Some(label));
+                        }
+                        Some(_) => {}
+                    }
+                    branches.push_back(branch_address);
                 }
-                if !instruction.falls_through() {
+                if !instruction.falls_through()
+                    || Arch::stops_fall_through(address + instruction.size())
+                {
                     break;
                 }
                 address += instruction.size();
             }
         }
+        report
     }
 
-    pub fn resolve_branch_address(&self, location: usize) -> Option<usize> {
-        Arch::disassemble(&self.state.rom[location..]).and_then(|instruction| {
-            instruction.branch_address().and_then(|unresolved_address| {
-                Arch::resolve_address(unresolved_address, location, &self.state)
-            })
+    /// Whether `address` starts a run of at least `TRAP_RUN_LENGTH` identical traps
+    fn is_trap_run(&self, address: usize) -> bool {
+        let byte = self.state.rom[address];
+        (address..address + TRAP_RUN_LENGTH).all(|address| {
+            address < self.state.rom.len()
+                && self.state.rom[address] == byte
+                && self
+                    .instruction_at(address)
+                    .is_some_and(|instruction| instruction.is_trap())
+        })
+    }
+
+    /// Whether any of the `size` bytes at `address` has been given a type other than code
+    fn overlaps_data(&self, address: usize, size: usize) -> bool {
+        let end = (address + size).min(self.state.rom.len());
+        self.state.byte_type[address..end].iter().any(|&byte_type| {
+            matches!(
+                byte_type,
+                ByteType::Data | ByteType::Text | ByteType::Pointer
+            )
         })
     }
 
-    fn align_address_to_valid_location(&self, address: usize) -> usize {
+    /// Groups the edits made since the last call into a single undo step
+    pub fn commit_edits(&mut self) {
+        if !self.pending_edits.is_empty() {
+            self.undo_stack
+                .push(std::mem::take(&mut self.pending_edits));
+            self.redo_stack.clear();
+        }
+    }
+
+    /// Forgets every edit made so far, e.g. after loading a project
+    pub fn clear_history(&mut self) {
+        self.pending_edits.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    /// Reverts the last undo step, returns false if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        self.commit_edits();
+        match self.undo_stack.pop() {
+            Some(edits) => {
+                let inverse = self.apply_edits(edits);
+                self.redo_stack.push(inverse);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the last undone step, returns false if there is nothing to redo
+    pub fn redo(&mut self) -> bool {
+        match self.redo_stack.pop() {
+            Some(edits) => {
+                let inverse = self.apply_edits(edits);
+                self.undo_stack.push(inverse);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies `edits` in reverse order, returns the edits that revert them
+    fn apply_edits(&mut self, edits: Vec<Edit>) -> Vec<Edit> {
+        for edit in edits.into_iter().rev() {
+            match edit {
+                Edit::ByteType(address, byte_type) => self.write_byte_type(address, byte_type),
+                Edit::Label(address, name) => self.write_label(address, name),
+                Edit::Comment(address, comment) => self.write_comment(address, comment),
+                Edit::Bank(location, bank) => self.write_bank(location, bank),
+                Edit::BankRange(start, range) => self.write_bank_range(start, range),
+                Edit::Byte(address, value) => self.write_byte(address, value),
+            }
+        }
+        std::mem::take(&mut self.pending_edits)
+    }
+
+    fn write_byte_type(&mut self, address: usize, byte_type: ByteType) {
+        let previous = std::mem::replace(&mut self.state.byte_type[address], byte_type);
+        if previous != byte_type {
+            self.pending_edits.push(Edit::ByteType(address, previous));
+        }
+    }
+
+    fn write_label(&mut self, address: usize, name: Option<String>) {
+        let previous = match name {
+            Some(name) => {
+                self.state.label_addresses.insert(address);
+                self.state.labels.insert(address, name)
+            }
+            None => {
+                self.state.label_addresses.remove(&address);
+                self.state.labels.remove(&address)
+            }
+        };
+        self.pending_edits.push(Edit::Label(address, previous));
+    }
+
+    fn write_comment(&mut self, address: usize, comment: Option<String>) {
+        let previous = match comment {
+            Some(comment) => self.state.comments.insert(address, comment),
+            None => self.state.comments.remove(&address),
+        };
+        self.pending_edits.push(Edit::Comment(address, previous));
+    }
+
+    fn write_bank(&mut self, location: usize, bank: Option<usize>) {
+        let previous = match bank {
+            Some(bank) => self.state.banks.insert(location, bank),
+            None => self.state.banks.remove(&location),
+        };
+        self.pending_edits.push(Edit::Bank(location, previous));
+    }
+
+    fn write_bank_range(&mut self, start: usize, range: Option<(usize, usize)>) {
+        let previous = match range {
+            Some(range) => self.state.bank_ranges.insert(start, range),
+            None => self.state.bank_ranges.remove(&start),
+        };
+        self.pending_edits.push(Edit::BankRange(start, previous));
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) {
+        let previous = std::mem::replace(&mut self.state.rom[address], value);
+        if previous == value {
+            return;
+        }
+        let original = *self.state.patches.entry(address).or_insert(previous);
+        if original == value {
+            self.state.patches.remove(&address);
+        }
+        // Decode again the instructions the byte is part of
+        let first = address.saturating_sub(Arch::MAX_INSTRUCTION_SIZE - 1);
+        for start in first..=address {
+            self.instructions[start] = Arch::disassemble(&self.state.rom[start..]);
+        }
+        self.pending_edits.push(Edit::Byte(address, previous));
+    }
+
+    /// Moves `address` back to the start of the instruction or data line it is in the middle
+    /// of, if any
+    pub fn align_address_to_valid_location(&self, address: usize) -> usize {
         match self.state.byte_type[address] {
-            ByteType::Data | ByteType::Code => address,
-            ByteType::Unknown => {
-                for back_offset in 1..3.min(address + 1) {
-                    if let Some(_) = Arch::disassemble(&self.state.rom[address - back_offset..]) {
-                        return address - back_offset;
+            ByteType::Data => return self.data_line_start(address),
+            ByteType::Text => return self.text_line_start(address),
+            ByteType::Pointer => return self.pointer_start(address),
+            ByteType::Unknown | ByteType::Code => {}
+        }
+        for back_offset in 1..3.min(address + 1) {
+            let offset_address = address - back_offset;
+            if self.state.byte_type[offset_address] == ByteType::Code {
+                if let Some(instruction) = self.instruction_at(offset_address) {
+                    if instruction.size() > back_offset {
+                        return offset_address;
                     }
                 }
-                address
             }
         }
+        address
+    }
+
+    /// Returns the address of the item following the one containing `address`
+    pub fn next_valid_location(&self, address: usize) -> usize {
+        let address = self.align_address_to_valid_location(address);
+        match self.state.byte_type[address] {
+            ByteType::Code => {
+                if let Some(instruction) = self.instruction_at(address) {
+                    return address + instruction.size();
+                }
+            }
+            ByteType::Data => return address + self.data_line_length(address),
+            ByteType::Text => return address + self.text_line_length(address),
+            ByteType::Pointer => return address + self.pointer_length(address),
+            ByteType::Unknown => {}
+        }
+        address + 1
+    }
+
+    /// Returns the address of the first byte of the data line containing `address`. Data lines
+    /// are aligned on `DATA_LINE_LENGTH` bytes and a label always starts a new line.
+    fn data_line_start(&self, address: usize) -> usize {
+        let mut start = address;
+        while !start.is_multiple_of(DATA_LINE_LENGTH)
+            && !self.state.labels.contains_key(&start)
+            && self.state.byte_type[start - 1] == ByteType::Data
+        {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Returns the number of bytes in the data line starting at `address`
+    fn data_line_length(&self, address: usize) -> usize {
+        let mut end = address + 1;
+        while end < self.state.rom.len()
+            && !end.is_multiple_of(DATA_LINE_LENGTH)
+            && !self.state.labels.contains_key(&end)
+            && self.state.byte_type[end] == ByteType::Data
+        {
+            end += 1;
+        }
+        end - address
+    }
+
+    /// Returns the address of the first byte of the pointer containing `address`. Pointers are
+    /// counted from the start of their table, and a label always starts a new table.
+    fn pointer_start(&self, address: usize) -> usize {
+        let mut table_start = address;
+        while table_start > 0
+            && !self.state.labels.contains_key(&table_start)
+            && self.state.byte_type[table_start - 1] == ByteType::Pointer
+        {
+            table_start -= 1;
+        }
+        address - (address - table_start) % POINTER_SIZE
+    }
+
+    /// Returns the number of bytes in the pointer starting at `address`, which is less than
+    /// `POINTER_SIZE` if the table ends in the middle of it
+    fn pointer_length(&self, address: usize) -> usize {
+        let mut end = address + 1;
+        while end < self.state.rom.len()
+            && end - address < POINTER_SIZE
+            && !self.state.labels.contains_key(&end)
+            && self.state.byte_type[end] == ByteType::Pointer
+        {
+            end += 1;
+        }
+        end - address
+    }
+
+    /// Returns the address of the first byte of the string containing `address`
+    fn text_line_start(&self, address: usize) -> usize {
+        let mut start = address;
+        while start > 0
+            && !self.state.labels.contains_key(&start)
+            && self.state.byte_type[start - 1] == ByteType::Text
+            && self.state.rom[start - 1] != TEXT_TERMINATOR
+        {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Returns the number of bytes in the string starting at `address`, including its
+    /// terminator
+    fn text_line_length(&self, address: usize) -> usize {
+        let mut end = address + 1;
+        while end < self.state.rom.len()
+            && self.state.rom[end - 1] != TEXT_TERMINATOR
+            && !self.state.labels.contains_key(&end)
+            && self.state.byte_type[end] == ByteType::Text
+        {
+            end += 1;
+        }
+        end - address
+    }
+
+    /// Returns the address of the next occurrence of `pattern` after `address`, wrapping around
+    /// at the end of the ROM. `None` bytes in the pattern match any byte.
+    pub fn find_bytes(&self, pattern: &[Option<u8>], address: usize) -> Option<usize> {
+        let rom = &self.state.rom;
+        if pattern.is_empty() || pattern.len() > rom.len() {
+            return None;
+        }
+        let last_start = rom.len() - pattern.len();
+        ((address + 1)..=last_start)
+            .chain(0..=address.min(last_start))
+            .find(|&start| self.bytes_match(pattern, start))
+    }
+
+    /// Whether the bytes at `start` match `pattern`, see `find_bytes`
+    pub fn bytes_match(&self, pattern: &[Option<u8>], start: usize) -> bool {
+        let rom = &self.state.rom;
+        !pattern.is_empty()
+            && start + pattern.len() <= rom.len()
+            && pattern
+                .iter()
+                .zip(&rom[start..])
+                .all(|(expected, &byte)| expected.is_none_or(|expected| expected == byte))
+    }
+
+    /// Returns the start of the next run of unknown bytes after the one `address` is in,
+    /// wrapping around at the end of the ROM
+    pub fn find_unknown(&self, address: usize) -> Option<usize> {
+        let rom_length = self.state.rom.len();
+        let is_run_start =
+            |start: usize| self.is_unknown(start) && (start == 0 || !self.is_unknown(start - 1));
+        ((address + 1)..rom_length)
+            .chain(0..=address.min(rom_length.saturating_sub(1)))
+            .find(|&start| is_run_start(start))
+    }
+
+    /// Returns the first labelled address after `address`, in the ROM
+    pub fn next_label(&self, address: usize) -> Option<usize> {
+        let rom_length = self.state.rom.len();
+        let next = address.checked_add(1)?;
+        self.state
+            .label_addresses
+            .range(next..rom_length.max(next))
+            .next()
+            .copied()
+    }
+
+    /// Returns the last labelled address before `address`, in the ROM
+    pub fn previous_label(&self, address: usize) -> Option<usize> {
+        let end = address.min(self.state.rom.len());
+        self.state.label_addresses.range(..end).next_back().copied()
+    }
+
+    /// Counts how many bytes have been identified as code or data
+    pub fn coverage(&self) -> Coverage {
+        let mut coverage = Coverage {
+            code: 0,
+            data: 0,
+            unknown: 0,
+        };
+        let rom_length = self.state.rom.len();
+        let mut address = 0;
+        while address < rom_length {
+            match self.state.byte_type[address] {
+                ByteType::Unknown => coverage.unknown += 1,
+                ByteType::Data | ByteType::Text | ByteType::Pointer => coverage.data += 1,
+                ByteType::Code => {
+                    // Only the first byte of an instruction is marked
+                    let size = self
+                        .instruction_at(address)
+                        .map_or(1, |instruction| instruction.size())
+                        .min(rom_length - address);
+                    coverage.code += size;
+                    address += size;
+                    continue;
+                }
+            }
+            address += 1;
+        }
+        coverage
+    }
+
+    /// Whether the byte at `address` has no type and isn't part of an instruction
+    fn is_unknown(&self, address: usize) -> bool {
+        self.state.byte_type[address] == ByteType::Unknown
+            && self.align_address_to_valid_location(address) == address
+    }
+
+    /// Iterates over the items to display starting at `address`
+    pub fn iter_from(&self, address: usize) -> AddressReprIter<'_, Arch> {
+        AddressReprIter {
+            disassembler: self,
+            address,
+            label_done: false,
+            layout: Layout::Listing,
+            collapse_padding: false,
+        }
+    }
+
+    /// Like `iter_from`, with data and unknown bytes grouped as `layout` says and the runs of
+    /// padding collapsed unless they have been expanded
+    pub fn iter_with_layout(&self, address: usize, layout: Layout) -> AddressReprIter<'_, Arch> {
+        AddressReprIter {
+            disassembler: self,
+            address,
+            label_done: false,
+            layout,
+            collapse_padding: true,
+        }
+    }
+
+    /// Like `align_address_to_valid_location`, for the lines of `layout`
+    pub fn align_to_line(&self, address: usize, layout: Layout) -> usize {
+        if let Some((start, _)) = self.collapsed_padding(address) {
+            start
+        } else if layout == Layout::HexDump && self.is_dumped(address) {
+            self.dump_line_start(address)
+        } else {
+            self.align_address_to_valid_location(address)
+        }
+    }
+
+    /// Like `next_valid_location`, for the lines of `layout`
+    pub fn next_line(&self, address: usize, layout: Layout) -> usize {
+        let address = self.align_to_line(address, layout);
+        if let Some((_, end)) = self.collapsed_padding(address) {
+            end
+        } else if layout == Layout::HexDump && self.is_dumped(address) {
+            address + self.dump_line_length(address)
+        } else {
+            self.next_valid_location(address)
+        }
+    }
+
+    /// Collapses the run of padding containing `address` if it is expanded, expands it
+    /// otherwise. Returns the start of the run, None if `address` isn't in one.
+    pub fn toggle_padding(&mut self, address: usize) -> Option<usize> {
+        let (start, end) = self.padding_run(address)?;
+        let expanded = self.expanded_run(address).is_some();
+        // The bounds recorded for runs changed since they were expanded may overlap this one
+        let overlapping: Vec<usize> = self
+            .expanded_padding
+            .range(..end)
+            .filter(|&(_, &expanded_end)| expanded_end > start)
+            .map(|(&expanded_start, _)| expanded_start)
+            .collect();
+        for expanded_start in overlapping {
+            self.expanded_padding.remove(&expanded_start);
+        }
+        if !expanded {
+            self.expanded_padding.insert(start, end);
+        }
+        Some(start)
+    }
+
+    /// Returns the start and end of the run of padding containing `address`, if it is long
+    /// enough to be collapsed. Padding is unknown bytes repeating one of `PADDING_BYTES`, a
+    /// label ends the run.
+    fn padding_run(&self, address: usize) -> Option<(usize, usize)> {
+        let byte = self.state.rom[address];
+        let is_padding =
+            |address: usize| self.state.rom[address] == byte && self.is_unknown(address);
+        if !PADDING_BYTES.contains(&byte) || !is_padding(address) {
+            return None;
+        }
+        let mut start = address;
+        while start > 0 && !self.state.labels.contains_key(&start) && is_padding(start - 1) {
+            start -= 1;
+        }
+        let mut end = address + 1;
+        while end < self.state.rom.len() && !self.state.labels.contains_key(&end) && is_padding(end)
+        {
+            end += 1;
+        }
+        if end - start >= PADDING_RUN_LENGTH {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the start and end of the expanded run of padding containing `address`, as
+    /// recorded when it was expanded so that the run isn't scanned for every byte of it
+    fn expanded_run(&self, address: usize) -> Option<(usize, usize)> {
+        self.expanded_padding
+            .range(..=address)
+            .next_back()
+            .map(|(&start, &end)| (start, end))
+            .filter(|&(_, end)| address < end)
+    }
+
+    /// Like `padding_run`, only for the runs shown collapsed
+    fn collapsed_padding(&self, address: usize) -> Option<(usize, usize)> {
+        if self.expanded_run(address).is_some() {
+            return None;
+        }
+        self.padding_run(address)
+    }
+
+    /// Whether the byte at `address` is shown in a hex dump row in the hex dump layout
+    fn is_dumped(&self, address: usize) -> bool {
+        self.state.byte_type[address] == ByteType::Data || self.is_unknown(address)
+    }
+
+    /// Returns the address of the first byte of the hex dump row containing `address`. Rows are
+    /// aligned on `DUMP_LINE_LENGTH` bytes, hold bytes of a single type and a label always
+    /// starts a new row.
+    fn dump_line_start(&self, address: usize) -> usize {
+        let byte_type = self.state.byte_type[address];
+        let mut start = address;
+        while !start.is_multiple_of(DUMP_LINE_LENGTH)
+            && !self.state.labels.contains_key(&start)
+            && self.state.byte_type[start - 1] == byte_type
+            && self.is_dumped(start - 1)
+            && self.collapsed_padding(start - 1).is_none()
+        {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Returns the number of bytes in the hex dump row starting at `address`, rows stop
+    /// before collapsed padding
+    fn dump_line_length(&self, address: usize) -> usize {
+        let byte_type = self.state.byte_type[address];
+        let mut end = address + 1;
+        while end < self.state.rom.len()
+            && !end.is_multiple_of(DUMP_LINE_LENGTH)
+            && !self.state.labels.contains_key(&end)
+            && self.state.byte_type[end] == byte_type
+            && self.collapsed_padding(end).is_none()
+        {
+            end += 1;
+        }
+        end - address
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum ByteType {
+pub struct AddressReprIter<'a, Arch: Architecture> {
+    disassembler: &'a Disassembler<Arch>,
+    address: usize,
+    /// Whether the label for the current address has already been produced
+    label_done: bool,
+    layout: Layout,
+    collapse_padding: bool,
+}
+
+impl<'a, Arch: Architecture> Iterator for AddressReprIter<'a, Arch> {
+    type Item = AddressRepr<'a, Arch::Instruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let state = &self.disassembler.state;
+        let address = self.address;
+        if address >= state.rom.len() {
+            return None;
+        }
+
+        if !self.label_done {
+            self.label_done = true;
+            if let Some(name) = state.labels.get(&address) {
+                return Some(AddressRepr::Label { address, name });
+            }
+        }
+
+        let byte = state.rom[address];
+        if self.collapse_padding {
+            if let Some((_, end)) = self.disassembler.collapsed_padding(address) {
+                self.address = end;
+                self.label_done = false;
+                let length = end - address;
+                return Some(AddressRepr::Padding {
+                    address,
+                    byte,
+                    length,
+                });
+            }
+        }
+        let dumped = self.layout == Layout::HexDump && self.disassembler.is_dumped(address);
+        let (repr, size) = match state.byte_type[address] {
+            _ if dumped => {
+                let size = self.disassembler.dump_line_length(address);
+                let bytes = &state.rom[address..address + size];
+                (AddressRepr::Dump { address, bytes }, size)
+            }
+            ByteType::Unknown => (AddressRepr::Unknown { address, byte }, 1),
+            ByteType::Data => {
+                let size = self.disassembler.data_line_length(address);
+                let bytes = &state.rom[address..address + size];
+                (AddressRepr::Data { address, bytes }, size)
+            }
+            ByteType::Text => {
+                let size = self.disassembler.text_line_length(address);
+                let bytes = &state.rom[address..address + size];
+                (AddressRepr::Text { address, bytes }, size)
+            }
+            ByteType::Pointer => {
+                let size = self.disassembler.pointer_length(address);
+                let bytes = &state.rom[address..address + size];
+                (AddressRepr::Pointer { address, bytes }, size)
+            }
+            ByteType::Code => match self.disassembler.instruction_at(address) {
+                Some(instruction) => {
+                    let size = instruction.size();
+                    let bytes = &state.rom[address..address + size];
+                    (
+                        AddressRepr::Code {
+                            address,
+                            bytes,
+                            instruction,
+                        },
+                        size,
+                    )
+                }
+                None => (AddressRepr::Undecoded { address, byte }, 1),
+            },
+        };
+        self.address += size;
+        self.label_done = false;
+        Some(repr)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteType {
     Unknown,
     Code,
     Data,
+    Text,
+    /// An entry of a table of pointers, such as a jump table
+    Pointer,
 }
 
-pub enum AddressRepr<'a> {
+pub enum AddressRepr<'a, Instruction> {
     Unknown {
         address: usize,
         byte: u8,
     },
     Data {
         address: usize,
-        byte: u8,
+        bytes: &'a [u8],
+    },
+    /// A string of printable characters, possibly ending with a terminator
+    Text {
+        address: usize,
+        bytes: &'a [u8],
+    },
+    /// A pointer, `bytes` is shorter than `POINTER_SIZE` if the table ends in the middle of it
+    Pointer {
+        address: usize,
+        bytes: &'a [u8],
     },
     Code {
         address: usize,
         bytes: &'a [u8],
-        instruction_name: &'a str,
-        arguments: &'a str,
+        instruction: Instruction,
+    },
+    /// A byte marked as code that doesn't decode to an instruction
+    Undecoded {
+        address: usize,
+        byte: u8,
+    },
+    /// A row of data or unknown bytes in the hex dump layout
+    Dump {
+        address: usize,
+        bytes: &'a [u8],
+    },
+    /// A collapsed run of `length` unknown bytes all equal to `byte`
+    Padding {
+        address: usize,
+        byte: u8,
+        length: usize,
     },
     Label {
+        address: usize,
         name: &'a str,
     },
 }
 
+/// How data and unknown bytes are grouped into lines
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// Data lines of `DATA_LINE_LENGTH` bytes and a line per unknown byte, like the assembly
+    Listing,
+    /// Rows of `DUMP_LINE_LENGTH` bytes, like a hex editor
+    HexDump,
+}
+
 pub trait Architecture {
+    /// The name the architecture is chosen by on the command line
+    const NAME: &'static str;
+    /// The size of the longest instruction, in bytes
+    const MAX_INSTRUCTION_SIZE: usize;
+
     type Instruction: self::Instruction;
 
     fn disassemble(bytes: &[u8]) -> Option<Self::Instruction>;
@@ -124,9 +1161,13 @@ pub trait Architecture {
         location: usize,
         state: &DisassemblerState,
     ) -> Option<usize>;
+
+    /// Whether following code must stop instead of falling through into `address`, such as
+    /// at the start of a vector the hardware jumps to
+    fn stops_fall_through(address: usize) -> bool;
 }
 
-pub trait Instruction {
+pub trait Instruction: Copy {
     /// The size of the instruction in bytes
     fn size(&self) -> usize;
 
@@ -136,9 +1177,502 @@ pub trait Instruction {
     /// The logical address that execution can jump to from this address, None if
     /// this isn't a branch instruction
     fn branch_address(&self) -> Option<LogicalAddress>;
+
+    /// Whether the branch is a subroutine call, which returns after the instruction
+    fn is_call(&self) -> bool;
+
+    /// The logical address of the memory this instruction loads from or stores to, None if it
+    /// doesn't access memory at a fixed address
+    fn data_address(&self) -> Option<LogicalAddress>;
+
+    /// Whether this is an opcode the processor doesn't implement
+    fn is_illegal(&self) -> bool;
+
+    /// Whether this is what erased or padding bytes decode to, which code only runs into by
+    /// accident, such as `RST 38` for `0xff` on the Game Boy
+    fn is_trap(&self) -> bool;
+
+    /// How long the instruction takes to execute
+    fn cycles(&self) -> Cycles;
+}
+
+/// Duration of an instruction in machine cycles
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cycles {
+    /// Duration when the instruction doesn't branch
+    pub base: usize,
+    /// Duration when the branch is taken, only set for conditional branches
+    pub taken: Option<usize>,
+}
+
+impl Cycles {
+    pub fn fixed(cycles: usize) -> Cycles {
+        Cycles {
+            base: cycles,
+            taken: None,
+        }
+    }
+
+    pub fn conditional(not_taken: usize, taken: usize) -> Cycles {
+        Cycles {
+            base: not_taken,
+            taken: Some(taken),
+        }
+    }
 }
 
 pub enum LogicalAddress {
     Absolute(usize),
     Relative(isize),
 }
+
+/// What `mark_code` ran into while following code
+#[derive(Default)]
+pub struct CodeReport {
+    /// How many bytes were marked as code
+    pub bytes: usize,
+    /// How many labels were created for branch targets
+    pub labels: usize,
+    /// Where following ran into an illegal opcode
+    pub illegal: Vec<usize>,
+    /// Where following ran into an instruction cut by the end of the ROM
+    pub truncated: Vec<usize>,
+    /// Where following ran into a run of traps, which is most likely padding
+    pub traps: Vec<usize>,
+    /// Where following stopped because an instruction would overlap bytes of another type
+    pub data_overlaps: Vec<usize>,
+}
+
+/// The number of bytes of each kind, text and pointers count as data
+pub struct Coverage {
+    pub code: usize,
+    pub data: usize,
+    pub unknown: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb::GameBoy;
+    use crate::listing::{Formatter, Immediates};
+
+    #[test]
+    fn mark_code_linear_sequence() {
+        // NOP; INC A; INC B; RET; NOP
+        let rom = [0x00, 0x3c, 0x04, 0xc9, 0x00];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom.to_vec());
+        disassembler.mark_code(0);
+
+        assert_eq!(
+            disassembler.state.byte_type,
+            vec![
+                ByteType::Code,
+                ByteType::Code,
+                ByteType::Code,
+                ByteType::Code,
+                ByteType::Unknown
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_jump_target_matches_follow() {
+        // NOP; JR -3 (back to the NOP); JR +0
+        let rom = vec![0x00, 0x18, 0xfd, 0x18, 0x00, 0x00];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(0);
+        disassembler.mark_code(3);
+
+        assert_eq!(disassembler.branch_target(1), Some(0));
+        assert_eq!(disassembler.label(0), Some("LOC_000000"));
+        assert_eq!(disassembler.branch_target(3), Some(5));
+        assert_eq!(disassembler.label(5), Some("LOC_000005"));
+
+        let format = |disassembler: &Disassembler<GameBoy>, address| {
+            let formatter = Formatter {
+                disassembler,
+                register_names: false,
+                immediates: Immediates::Hex,
+            };
+            let instruction = disassembler.instruction_at(address).unwrap();
+            formatter.format_instruction(address, &instruction)
+        };
+        assert_eq!(format(&disassembler, 1), "JR    LOC_000000 (.-1)");
+        assert_eq!(format(&disassembler, 3), "JR    LOC_000005 (.+2)");
+
+        // JR +16, past the end
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0x18, 0x10]);
+        disassembler.mark_code(0);
+        assert_eq!(format(&disassembler, 0), "JR    (past end) (.+18)");
+    }
+
+    #[test]
+    fn move_down_from_middle_of_data_line() {
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0; 20]);
+        for address in 0..20 {
+            disassembler.mark_data(address);
+        }
+
+        assert_eq!(disassembler.next_valid_location(3), 8);
+        assert_eq!(disassembler.next_valid_location(8), 16);
+        assert_eq!(disassembler.next_valid_location(17), 20);
+    }
+
+    #[test]
+    fn move_up_from_middle_of_data_line() {
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0; 20]);
+        for address in 4..20 {
+            disassembler.mark_data(address);
+        }
+
+        // The first line only starts at 4, where the data does
+        assert_eq!(disassembler.align_address_to_valid_location(6), 4);
+        assert_eq!(disassembler.align_address_to_valid_location(11), 8);
+        assert_eq!(disassembler.align_address_to_valid_location(8), 8);
+        assert_eq!(disassembler.align_address_to_valid_location(3), 3);
+    }
+
+    #[test]
+    fn move_through_middle_of_text() {
+        let rom = b"\x00HELLO\x00WORLD\x00".to_vec();
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_text(1);
+        disassembler.mark_text(7);
+
+        assert_eq!(disassembler.next_valid_location(3), 7);
+        assert_eq!(disassembler.next_valid_location(9), 13);
+        assert_eq!(disassembler.align_address_to_valid_location(6), 1);
+        assert_eq!(disassembler.align_address_to_valid_location(12), 7);
+    }
+
+    #[test]
+    fn move_down_from_middle_of_instruction() {
+        // JP 0003; NOP
+        let rom = vec![0xc3, 0x03, 0x00, 0x00];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(0);
+
+        assert_eq!(disassembler.next_valid_location(1), 3);
+        assert_eq!(disassembler.next_valid_location(2), 3);
+        assert_eq!(disassembler.align_address_to_valid_location(2), 0);
+    }
+
+    #[test]
+    fn find_unknown_skips_the_current_run() {
+        // LD A, 05 at 2, its operand isn't unknown
+        let mut rom = vec![0; 10];
+        rom[2] = 0x3e;
+        rom[3] = 0x05;
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.set_byte_type(2, ByteType::Code);
+        disassembler.set_byte_type(6, ByteType::Data);
+
+        // Runs of unknown bytes start at 0, 4 and 7
+        assert_eq!(disassembler.find_unknown(0), Some(4));
+        assert_eq!(disassembler.find_unknown(5), Some(7));
+        assert_eq!(disassembler.find_unknown(8), Some(0));
+        assert_eq!(disassembler.find_unknown(3), Some(4));
+
+        let coverage = disassembler.coverage();
+        assert_eq!((coverage.code, coverage.data, coverage.unknown), (2, 1, 7));
+    }
+
+    #[test]
+    fn mark_code_stops_at_data() {
+        // NOP; JR +1; NOP; LD A, 05 with its operand marked as data
+        let rom = vec![0x00, 0x18, 0x01, 0x00, 0x3e, 0x05];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.set_byte_type(5, ByteType::Data);
+        let report = disassembler.mark_code(0);
+
+        assert_eq!(report.data_overlaps, vec![4]);
+        assert_eq!((report.bytes, report.labels), (3, 1));
+        assert_eq!(disassembler.byte_type(4), ByteType::Unknown);
+        assert_eq!(disassembler.byte_type(5), ByteType::Data);
+
+        // Marking the data itself is deliberate
+        disassembler.mark_code(4);
+        assert_eq!(disassembler.byte_type(4), ByteType::Code);
+    }
+
+    #[test]
+    fn mark_code_reports_illegal_opcodes() {
+        // NOP; illegal 0xd3; NOP
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0x00, 0xd3, 0x00]);
+        let report = disassembler.mark_code(0);
+
+        assert_eq!(report.illegal, vec![1]);
+        assert_eq!(report.bytes, 1);
+        assert_eq!(disassembler.byte_type(1), ByteType::Data);
+        assert_eq!(disassembler.byte_type(2), ByteType::Unknown);
+
+        // NOP; LD BC with its operand cut by the end of the ROM
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0x00, 0x01, 0x34]);
+        let report = disassembler.mark_code(0);
+
+        assert!(report.illegal.is_empty());
+        assert_eq!(report.truncated, vec![1]);
+        assert_eq!(disassembler.byte_type(1), ByteType::Data);
+    }
+
+    #[test]
+    fn call_targets_are_subroutines() {
+        // JR +3; CALL 0005; RET; RET
+        let rom = vec![0x18, 0x03, 0xcd, 0x05, 0x00, 0xc9, 0xc9];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(2);
+        disassembler.mark_code(0);
+
+        assert_eq!(disassembler.label(5), Some("SUB_000005"));
+
+        // Found through the jump first, then through the call
+        let mut disassembler = Disassembler::<GameBoy>::new(disassembler.rom().to_vec());
+        disassembler.mark_code(0);
+        assert_eq!(disassembler.label(5), Some("LOC_000005"));
+        disassembler.mark_code(2);
+        assert_eq!(disassembler.label(5), Some("SUB_000005"));
+    }
+
+    #[test]
+    fn bank_ranges_split_and_undo() {
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0; 0x20]);
+        disassembler.set_bank_range(0x00, 0x10, 1);
+        disassembler.set_bank(0x08, 3);
+        disassembler.commit_edits();
+        disassembler.set_bank_range(0x04, 0x08, 2);
+        disassembler.commit_edits();
+
+        assert_eq!(disassembler.bank(0x02), Some(1));
+        assert_eq!(disassembler.bank(0x05), Some(2));
+        assert_eq!(disassembler.bank(0x08), Some(3));
+        assert_eq!(disassembler.bank(0x0c), Some(1));
+        assert_eq!(disassembler.bank(0x10), None);
+
+        disassembler.undo();
+        assert_eq!(disassembler.bank(0x05), Some(1));
+        assert_eq!(
+            disassembler.bank_ranges().collect::<Vec<_>>(),
+            vec![(0x00, 0x10, 1)]
+        );
+    }
+
+    #[test]
+    fn branches_out_of_the_rom_are_not_labelled() {
+        // JR -128 from the start of the ROM
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0x18, 0x80, 0x00]);
+        disassembler.mark_code(0);
+        assert_eq!(disassembler.labels().count(), 0);
+
+        // JP 3000 in a ROM much shorter than that
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0xc3, 0x00, 0x30, 0x00]);
+        disassembler.mark_code(0);
+        assert_eq!(disassembler.labels().count(), 0);
+    }
+
+    #[test]
+    fn patches_decode_and_undo() {
+        // LD A, 05; RET
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0x3e, 0x05, 0xc9]);
+        disassembler.patch_byte(0x00, 0x3c);
+        disassembler.commit_edits();
+
+        // INC A; DEC B; RET
+        assert_eq!(disassembler.rom(), &[0x3c, 0x05, 0xc9]);
+        assert_eq!(disassembler.instruction_at(0).map(|i| i.size()), Some(1));
+        assert_eq!(
+            disassembler.patches().collect::<Vec<_>>(),
+            vec![(0x00, 0x3e)]
+        );
+        assert_eq!(disassembler.original_rom(), vec![0x3e, 0x05, 0xc9]);
+
+        // Writing the original value back isn't a patch anymore
+        disassembler.patch_byte(0x00, 0x3e);
+        disassembler.commit_edits();
+        assert!(!disassembler.is_patched(0x00));
+
+        disassembler.undo();
+        assert!(disassembler.is_patched(0x00));
+        disassembler.undo();
+        assert_eq!(disassembler.rom(), &[0x3e, 0x05, 0xc9]);
+        assert_eq!(disassembler.instruction_at(0).map(|i| i.size()), Some(2));
+        assert_eq!(disassembler.patches().count(), 0);
+    }
+
+    #[test]
+    fn padding_is_collapsed() {
+        // RET; 0x40 bytes of 0xff padding; NOP
+        let mut rom = vec![0xc9];
+        rom.extend(vec![0xff; 0x40]);
+        rom.push(0x00);
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(0);
+
+        assert!(matches!(
+            disassembler.iter_with_layout(1, Layout::Listing).next(),
+            Some(AddressRepr::Padding {
+                address: 1,
+                byte: 0xff,
+                length: 0x40
+            })
+        ));
+        assert_eq!(disassembler.align_to_line(0x20, Layout::Listing), 1);
+        assert_eq!(disassembler.next_line(1, Layout::HexDump), 0x41);
+        // Exports still see every byte
+        assert!(matches!(
+            disassembler.iter_from(1).next(),
+            Some(AddressRepr::Unknown { address: 1, .. })
+        ));
+
+        assert_eq!(disassembler.toggle_padding(0x20), Some(1));
+        assert_eq!(disassembler.next_line(1, Layout::Listing), 2);
+        assert_eq!(disassembler.toggle_padding(0x20), Some(1));
+        assert_eq!(disassembler.next_line(1, Layout::Listing), 0x41);
+        assert_eq!(disassembler.toggle_padding(0), None);
+
+        // A label splitting an expanded run, collapsing either half collapses both
+        assert_eq!(disassembler.toggle_padding(0x20), Some(1));
+        disassembler.set_label(0x21, "split".to_string());
+        assert_eq!(disassembler.next_line(0x21, Layout::Listing), 0x22);
+        assert_eq!(disassembler.toggle_padding(0x30), Some(0x21));
+        assert_eq!(disassembler.next_line(1, Layout::Listing), 0x21);
+        assert_eq!(disassembler.next_line(0x21, Layout::Listing), 0x41);
+    }
+
+    #[test]
+    fn analyze_goes_through_marked_code() {
+        // JP 0004; NOP; RET
+        let rom = vec![0xc3, 0x04, 0x00, 0x00, 0xc9];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.set_byte_type(0, ByteType::Code);
+
+        assert_eq!(disassembler.mark_code(0).bytes, 0);
+        assert_eq!(disassembler.byte_type(4), ByteType::Unknown);
+
+        let report = disassembler.analyze(&[0], None);
+        assert_eq!(report.bytes, 1);
+        assert_eq!(report.labels, 1);
+        assert_eq!(disassembler.byte_type(4), ByteType::Code);
+    }
+
+    #[test]
+    fn following_stops_at_vector_boundaries() {
+        let mut rom = vec![0x00; 0x300];
+        // RST 38; RET
+        rom[0x200..0x202].copy_from_slice(&[0xff, 0xc9]);
+        // The handler jumps to its real body: JP 0250
+        rom[0x38..0x3b].copy_from_slice(&[0xc3, 0x50, 0x02]);
+        rom[0x250] = 0xc9;
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+
+        disassembler.mark_code(0x200);
+        assert_eq!(disassembler.label(0x38), Some("SUB_000038"));
+        assert_eq!(disassembler.byte_type(0x201), ByteType::Code);
+        assert_eq!(disassembler.byte_type(0x250), ByteType::Code);
+
+        // A handler made of NOPs doesn't run into the next vector
+        let report = disassembler.mark_code(0x08);
+        assert_eq!(report.bytes, 8);
+        assert_eq!(disassembler.byte_type(0x10), ByteType::Unknown);
+
+        // Nor does the entry point run into the header
+        let report = disassembler.mark_code(0x100);
+        assert_eq!(report.bytes, 4);
+        assert_eq!(disassembler.byte_type(0x104), ByteType::Unknown);
+    }
+
+    #[test]
+    fn following_stops_at_rst_38_padding() {
+        // NOP; RST 38; RET
+        let mut rom = vec![0x00, 0xff, 0xc9];
+        // NOP; then 0xff padding
+        rom.extend([0x00, 0xff, 0xff, 0xff, 0xff]);
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+
+        // A single RST 38 is code
+        disassembler.mark_code(0);
+        assert_eq!(disassembler.byte_type(2), ByteType::Code);
+
+        let report = disassembler.mark_code(3);
+        assert_eq!(report.bytes, 1);
+        assert_eq!(report.traps, vec![4]);
+        assert_eq!(disassembler.byte_type(4), ByteType::Unknown);
+    }
+
+    #[test]
+    fn data_xrefs_index_loads_and_stores() {
+        // LD A, (0009); LD (0009), A; LDH A, (44); RET; data
+        let rom = vec![0xfa, 0x09, 0x00, 0xea, 0x09, 0x00, 0xf0, 0x44, 0xc9, 0x12];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(0);
+        disassembler.update_xrefs();
+
+        assert_eq!(disassembler.data_xrefs(9), &[0, 3]);
+        assert!(disassembler.xrefs(9).is_empty());
+        // System addresses aren't in the ROM
+        assert_eq!(disassembler.data_xrefs(0x44), &[] as &[usize]);
+    }
+
+    #[test]
+    fn label_neighbours() {
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0; 0x10]);
+        disassembler.set_label(0x08, "b".to_string());
+        disassembler.set_label(0x02, "a".to_string());
+        // Never gone to, e.g. imported from symbols for a larger ROM
+        disassembler.set_label(0x40, "outside".to_string());
+
+        assert_eq!(disassembler.next_label(0x00), Some(0x02));
+        assert_eq!(disassembler.next_label(0x02), Some(0x08));
+        assert_eq!(disassembler.next_label(0x08), None);
+        assert_eq!(disassembler.previous_label(0x0f), Some(0x08));
+        assert_eq!(disassembler.previous_label(0x50), Some(0x08));
+        assert_eq!(disassembler.previous_label(0x08), Some(0x02));
+        assert_eq!(disassembler.previous_label(0x02), None);
+
+        // Undoing a label takes it out of the ones stepped through
+        disassembler.commit_edits();
+        disassembler.set_label(0x0c, "c".to_string());
+        disassembler.commit_edits();
+        assert_eq!(disassembler.next_label(0x08), Some(0x0c));
+        assert!(disassembler.undo());
+        assert_eq!(disassembler.next_label(0x08), None);
+    }
+
+    proptest::proptest! {
+        /// Corrupt or truncated ROMs must not crash the interactive view, which decodes and
+        /// draws from arbitrary offsets
+        #[test]
+        fn random_roms_never_panic(
+            rom in proptest::collection::vec(proptest::num::u8::ANY, 0..0x200),
+            start in proptest::num::usize::ANY,
+        ) {
+            let mut disassembler = Disassembler::<GameBoy>::new(rom.clone());
+            for address in 0..rom.len() {
+                if let Some(instruction) = disassembler.instruction_at(address) {
+                    proptest::prop_assert!(address + instruction.size() <= rom.len());
+                }
+            }
+
+            if !rom.is_empty() {
+                let start = start % rom.len();
+                disassembler.mark_code(start);
+                disassembler.analyze(&[start], Some(start));
+                // Branches out of the ROM must neither be followed nor labelled
+                let rom_length = rom.len();
+                proptest::prop_assert!(disassembler
+                    .labels()
+                    .all(|(address, _)| address < rom_length));
+                for address in 0..rom_length {
+                    if let Some(target) = disassembler.branch_target(address) {
+                        proptest::prop_assert!(target < rom_length);
+                    }
+                }
+                for layout in [Layout::Listing, Layout::HexDump] {
+                    let line = disassembler.align_to_line(start, layout);
+                    disassembler.next_line(line, layout);
+                    disassembler.iter_with_layout(line, layout).for_each(drop);
+                }
+            }
+            disassembler.iter_from(0).for_each(drop);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Export of the call graph in the DOT format of Graphviz.
+//!
+//! ```text
+//! digraph calls {
+//!     node [shape=box];
+//!     "Entry";
+//!     "Entry" -> "SUB_000160";
+//! }
+//! ```
+//!
+//! Nodes are the entry points and the subroutines, the targets of calls. A subroutine is made
+//! of the code reachable from its start without calling, by falling through or jumping, up to
+//! the start of another subroutine. Edges go from each subroutine to the ones it calls.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::disassembler::{ByteType, Disassembler, Instruction};
+use crate::gb::{GameBoy, ENTRY_POINTS};
+
+pub fn export(path: &Path, disassembler: &Disassembler<GameBoy>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    let graph = call_graph(disassembler);
+    let name = |address: usize| match disassembler.label(address) {
+        Some(label) => quote(label),
+        None => quote(&format!("SUB_{:06x}", address)),
+    };
+    writeln!(file, "digraph calls {{")?;
+    writeln!(file, "    node [shape=box];")?;
+    for (&function, callees) in &graph {
+        writeln!(file, "    {};", name(function))?;
+        for &callee in callees {
+            writeln!(file, "    {} -> {};", name(function), name(callee))?;
+        }
+    }
+    writeln!(file, "}}")?;
+
+    file.flush()
+}
+
+/// Returns the subroutines called by each function, by start of the function
+fn call_graph(disassembler: &Disassembler<GameBoy>) -> BTreeMap<usize, BTreeSet<usize>> {
+    let rom_length = disassembler.rom().len();
+    let is_code = |address: usize| disassembler.byte_type(address) == ByteType::Code;
+
+    let mut starts: BTreeSet<usize> = ENTRY_POINTS
+        .iter()
+        .map(|&(address, _)| address)
+        .filter(|&address| address < rom_length && is_code(address))
+        .collect();
+    for address in (0..rom_length).filter(|&address| is_code(address)) {
+        let is_call = disassembler
+            .instruction_at(address)
+            .is_some_and(|instruction| instruction.is_call());
+        if let Some(target) = disassembler.branch_target(address).filter(|_| is_call) {
+            if target < rom_length && is_code(target) {
+                starts.insert(target);
+            }
+        }
+    }
+
+    starts
+        .iter()
+        .map(|&start| {
+            let mut callees = BTreeSet::new();
+            let mut visited = BTreeSet::new();
+            let mut queue = VecDeque::from([start]);
+            while let Some(address) = queue.pop_front() {
+                if address >= rom_length || !is_code(address) || !visited.insert(address) {
+                    continue;
+                }
+                let instruction = match disassembler.instruction_at(address) {
+                    Some(instruction) => instruction,
+                    None => continue,
+                };
+                match disassembler.branch_target(address) {
+                    Some(target) if instruction.is_call() => {
+                        callees.insert(target);
+                    }
+                    // Jumps to other subroutines are tail calls, they don't make them part of
+                    // this one
+                    Some(target) if !starts.contains(&target) => queue.push_back(target),
+                    _ => {}
+                }
+                let next = address + instruction.size();
+                if instruction.falls_through() && !starts.contains(&next) {
+                    queue.push_back(next);
+                }
+            }
+            callees.retain(|callee| starts.contains(callee));
+            (start, callees)
+        })
+        .collect()
+}
+
+/// Quotes a name as a DOT identifier
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
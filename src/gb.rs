@@ -1,11 +1,318 @@
+// Variants are named after the instruction mnemonics
+#![allow(clippy::upper_case_acronyms)]
+
 use std::convert::TryInto;
 use std::fmt;
 
-use crate::disassembler::{Architecture, Instruction, LogicalAddress};
+use crate::disassembler::{
+    Architecture, ByteType, CodeReport, Cycles, Disassembler, Instruction, LogicalAddress,
+};
+
+/// Size of a ROM bank, and of the window at 0x4000 where switchable banks are mapped
+pub const BANK_SIZE: usize = 0x4000;
+
+/// Size of the slots of the reset vectors and interrupt handlers, which start every 8 bytes
+/// from 0x00 up to `VECTORS_END`
+const VECTOR_SIZE: usize = 8;
+const VECTORS_END: usize = 0x68;
+
+/// Where the cartridge header starts, right after the 4 bytes of the entry point
+const HEADER_START: usize = 0x104;
+
+/// Addresses where the hardware starts executing code: the reset vectors, the interrupt
+/// handlers and the entry point, with their usual names
+pub const ENTRY_POINTS: [(usize, &str); 14] = [
+    (0x00, "RST_00"),
+    (0x08, "RST_08"),
+    (0x10, "RST_10"),
+    (0x18, "RST_18"),
+    (0x20, "RST_20"),
+    (0x28, "RST_28"),
+    (0x30, "RST_30"),
+    (0x38, "RST_38"),
+    (0x40, "VBlankInterrupt"),
+    (0x48, "STATInterrupt"),
+    (0x50, "TimerInterrupt"),
+    (0x58, "SerialInterrupt"),
+    (0x60, "JoypadInterrupt"),
+    (ENTRY_POINT, "Entry"),
+];
+
+/// Where execution starts after the boot ROM
+pub const ENTRY_POINT: usize = 0x100;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct UnmappedAddress(pub u16);
 
+pub enum ResolvedAddress {
+    Physical(usize),
+    UnknownBank(u16),
+    /// A ROM address past the end of the ROM file, e.g. in a bank it doesn't have
+    PastEnd(usize),
+    /// An address outside of the ROM, with the memory region it is in
+    System(MemoryRegion, u16),
+}
+
+/// The parts of the address space past the ROM
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryRegion {
+    VRAM,
+    /// RAM on the cartridge, usually battery-backed
+    ExternalRAM,
+    WRAM,
+    /// Mirror of the WRAM from 0xe000
+    EchoRAM,
+    OAM,
+    /// The gap between the OAM and the I/O registers
+    Unusable,
+    /// The hardware registers, including rIE at 0xffff
+    IO,
+    HRAM,
+}
+
+impl MemoryRegion {
+    /// Returns the region `address` is in, None for ROM addresses
+    pub fn of(address: u16) -> Option<MemoryRegion> {
+        Some(match address {
+            0x0000..=0x7fff => return None,
+            0x8000..=0x9fff => MemoryRegion::VRAM,
+            0xa000..=0xbfff => MemoryRegion::ExternalRAM,
+            0xc000..=0xdfff => MemoryRegion::WRAM,
+            0xe000..=0xfdff => MemoryRegion::EchoRAM,
+            0xfe00..=0xfe9f => MemoryRegion::OAM,
+            0xfea0..=0xfeff => MemoryRegion::Unusable,
+            0xff00..=0xff7f | 0xffff => MemoryRegion::IO,
+            0xff80..=0xfffe => MemoryRegion::HRAM,
+        })
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemoryRegion::VRAM => "VRAM",
+            MemoryRegion::ExternalRAM => "SRAM",
+            MemoryRegion::WRAM => "WRAM",
+            MemoryRegion::EchoRAM => "ECHO",
+            MemoryRegion::OAM => "OAM",
+            MemoryRegion::Unusable => "UNUSABLE",
+            MemoryRegion::IO => "IO",
+            MemoryRegion::HRAM => "HRAM",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ResolvedAddress {
+    pub fn get(&self) -> Option<usize> {
+        match self {
+            &Self::Physical(address) => Some(address),
+            _ => None,
+        }
+    }
+}
+
+/// Finds where in the ROM `address` points to when it is used by the instruction at `read_at`,
+/// for the viewer. Banked addresses of code whose bank isn't known are in the current bank.
+pub fn resolve_physical_address(
+    disassembler: &Disassembler<GameBoy>,
+    read_at: usize,
+    address: UnmappedAddress,
+) -> ResolvedAddress {
+    let bank = disassembler.bank(read_at).or(disassembler.current_bank());
+    resolve(read_at, address, bank, disassembler.rom().len())
+}
+
+/// Resolves `address` for the instruction at `read_at`, taking banked addresses to be in
+/// `bank`. Shared by the analysis and the viewer so that they agree on where branches go.
+fn resolve(
+    read_at: usize,
+    address: UnmappedAddress,
+    bank: Option<usize>,
+    rom_length: usize,
+) -> ResolvedAddress {
+    if let Some(region) = MemoryRegion::of(address.0) {
+        return ResolvedAddress::System(region, address.0);
+    }
+    // Code in the 0x4000 window of the file is already in the bank, so we know its number
+    let bank = if address.0 < 0x4000 {
+        0
+    } else if (0x4000..0x8000).contains(&read_at) {
+        read_at / BANK_SIZE
+    } else {
+        match bank {
+            Some(bank) => bank,
+            None => return ResolvedAddress::UnknownBank(address.0 & 0x3fff),
+        }
+    };
+    // Only banks too large to be addressed overflow
+    let physical = banked_address(bank, address.0).unwrap_or(usize::MAX);
+    if physical < rom_length {
+        ResolvedAddress::Physical(physical)
+    } else {
+        ResolvedAddress::PastEnd(physical)
+    }
+}
+
+/// Converts an address in `bank`, as the CPU sees it, to the address in the ROM. Bank 0 stands
+/// for the whole ROM window, as when there is no bank switching. None outside of the ROM
+/// windows, or if the bank is too large to be addressed.
+pub fn banked_address(bank: usize, address: u16) -> Option<usize> {
+    match address {
+        0x0000..=0x3fff => Some(address as usize),
+        0x4000..=0x7fff if bank == 0 => Some(address as usize),
+        0x4000..=0x7fff => bank
+            .checked_mul(BANK_SIZE)?
+            .checked_add(address as usize - BANK_SIZE),
+        _ => None,
+    }
+}
+
+/// Marks the code reachable from the reset vectors, the interrupt handlers, the entry point
+/// and `target`, naming the entry points that have no label yet. Entry points marked as data
+/// are skipped, only `target` may be turned from data into code. Used when starting a new
+/// project and to analyze the whole program again.
+pub fn analyze_entry_points(
+    disassembler: &mut Disassembler<GameBoy>,
+    target: Option<usize>,
+) -> CodeReport {
+    let mut starts = Vec::new();
+    for &(address, name) in ENTRY_POINTS.iter() {
+        if address >= disassembler.rom().len() {
+            continue;
+        }
+        // Vectors the user marked as data are left alone
+        match disassembler.byte_type(address) {
+            ByteType::Unknown | ByteType::Code => {}
+            ByteType::Data | ByteType::Text | ByteType::Pointer => continue,
+        }
+        if disassembler.label(address).is_none() {
+            disassembler.set_label(address, name.to_string());
+        }
+        starts.push(address);
+    }
+    starts.extend(target);
+    let report = disassembler.analyze(&starts, target);
+    disassembler.update_xrefs();
+    report
+}
+
+/// Returns where the jump at the entry point goes, the start of the program proper. The entry
+/// point is almost always `NOP; JP Main`, None if it isn't a jump into the ROM.
+pub fn entry_jump_target(disassembler: &Disassembler<GameBoy>) -> Option<usize> {
+    let mut address = ENTRY_POINT;
+    let instruction = disassembler.instruction_at(address);
+    if matches!(instruction, Some(GBInstruction::NOP | GBInstruction::DI)) {
+        address += 1;
+    }
+    let target = disassembler.instruction_at(address)?.jump_address()?;
+    resolve_physical_address(disassembler, address, target).get()
+}
+
+/// Finds the instruction the flag tested by the conditional branch at `address` comes from,
+/// walking back through the code falling through into the branch. Calls are returned too as
+/// the flag may come from the subroutine. None if the walk leaves the code before finding one.
+pub fn find_flag_setter(disassembler: &Disassembler<GameBoy>, address: usize) -> Option<usize> {
+    let flag = disassembler.instruction_at(address)?.tested_flag()?;
+    let mut address = address;
+    while address > 0 {
+        let previous = disassembler.align_address_to_valid_location(address - 1);
+        if disassembler.byte_type(previous) != ByteType::Code {
+            return None;
+        }
+        let instruction = disassembler.instruction_at(previous)?;
+        // Only code running into `address` counts, not the end of a jump
+        if previous + instruction.size() != address || !instruction.falls_through() {
+            return None;
+        }
+        if instruction.flags_affected().get(flag) != FlagEffect::Unchanged || instruction.is_call()
+        {
+            return Some(previous);
+        }
+        address = previous;
+    }
+    None
+}
+
+/// Returns the name of the LCD timing register, rSTAT, rLY or rLYC, accessed by `instruction`
+pub fn lcd_timing_register(instruction: &GBInstruction) -> Option<&'static str> {
+    instruction
+        .first_argument()
+        .into_iter()
+        .chain(instruction.second_argument())
+        .find_map(|argument| match argument {
+            Argument::Address(UnmappedAddress(address @ (0xff41 | 0xff44 | 0xff45))) => {
+                hardware_register_name(address)
+            }
+            _ => None,
+        })
+}
+
+/// Returns the name of the memory-mapped hardware register at `address`, using the names from
+/// the usual `hardware.inc`
+pub fn hardware_register_name(address: u16) -> Option<&'static str> {
+    Some(match address {
+        0xff00 => "rP1",
+        0xff01 => "rSB",
+        0xff02 => "rSC",
+        0xff04 => "rDIV",
+        0xff05 => "rTIMA",
+        0xff06 => "rTMA",
+        0xff07 => "rTAC",
+        0xff0f => "rIF",
+        0xff10 => "rNR10",
+        0xff11 => "rNR11",
+        0xff12 => "rNR12",
+        0xff13 => "rNR13",
+        0xff14 => "rNR14",
+        0xff16 => "rNR21",
+        0xff17 => "rNR22",
+        0xff18 => "rNR23",
+        0xff19 => "rNR24",
+        0xff1a => "rNR30",
+        0xff1b => "rNR31",
+        0xff1c => "rNR32",
+        0xff1d => "rNR33",
+        0xff1e => "rNR34",
+        0xff20 => "rNR41",
+        0xff21 => "rNR42",
+        0xff22 => "rNR43",
+        0xff23 => "rNR44",
+        0xff24 => "rNR50",
+        0xff25 => "rNR51",
+        0xff26 => "rNR52",
+        0xff40 => "rLCDC",
+        0xff41 => "rSTAT",
+        0xff42 => "rSCY",
+        0xff43 => "rSCX",
+        0xff44 => "rLY",
+        0xff45 => "rLYC",
+        0xff46 => "rDMA",
+        0xff47 => "rBGP",
+        0xff48 => "rOBP0",
+        0xff49 => "rOBP1",
+        0xff4a => "rWY",
+        0xff4b => "rWX",
+        0xff4d => "rKEY1",
+        0xff4f => "rVBK",
+        0xff51 => "rHDMA1",
+        0xff52 => "rHDMA2",
+        0xff53 => "rHDMA3",
+        0xff54 => "rHDMA4",
+        0xff55 => "rHDMA5",
+        0xff56 => "rRP",
+        0xff68 => "rBCPS",
+        0xff69 => "rBCPD",
+        0xff6a => "rOCPS",
+        0xff6b => "rOCPD",
+        0xff70 => "rSVBK",
+        0xff76 => "rPCM12",
+        0xff77 => "rPCM34",
+        0xffff => "rIE",
+        _ => return None,
+    })
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GBInstruction {
     NOP,                         // 00
@@ -16,15 +323,33 @@ pub enum GBInstruction {
     INC8(Reg8),                  // 04 0C 14 1C 24 2C 34 4C
     DEC8(Reg8),                  // 05 0D 15 1D 25 2D 35 3D
     LDd8(Reg8, u8),              // 06 0E 16 1E 26 2E 36 3E
+    RLCA,                        // 07
+    LDa16SP(UnmappedAddress),    // 08
     ADDHL(Reg16),                // 09 19 29 39
+    LDAi16(Reg16),               // 0A 1A
+    RRCA,                        // 0F
+    STOP,                        // 10
+    RLA,                         // 17
     JRr8(i8),                    // 18
+    RRA,                         // 1F
     JRNZr8(i8),                  // 20
     LDHLincA,                    // 22
+    DAA,                         // 27
     JRZr8(i8),                   // 28
-    LDAHLdec,                    // 2A
+    LDAHLinc,                    // 2A
+    CPL,                         // 2F
+    JRNCr8(i8),                  // 30
     LDHLdecA,                    // 32
+    SCF,                         // 37
+    JRCr8(i8),                   // 38
+    LDAHLdec,                    // 3A
+    CCF,                         // 3F
     LD(Reg8, Reg8),              // 40 to 7F except 76
+    HALT,                        // 76
+    ADD(Reg8),                   // 80 to 87
+    ADC(Reg8),                   // 88 to 8F
     SUB(Reg8),                   // 90 to 97
+    SBC(Reg8),                   // 98 to 9F
     AND(Reg8),                   // A0 to A7
     XOR(Reg8),                   // A8 to AF
     OR(Reg8),                    // B0 to B7
@@ -32,57 +357,135 @@ pub enum GBInstruction {
     RETNZ,                       // C0
     JPNZa16(UnmappedAddress),    // C2
     JPa16(UnmappedAddress),      // C3
+    CALLNZa16(UnmappedAddress),  // C4
+    ADDd8(u8),                   // C6
     RST(ResetVector),            // C7 CF D7 DF E7 EF F7 FF
+    RETZ,                        // C8
     RET,                         // C9
     JPZa16(UnmappedAddress),     // CA
     Special(SpecialInstruction), // CB xx
+    CALLZa16(UnmappedAddress),   // CC
     CALLa16(UnmappedAddress),    // CD
+    ADCd8(u8),                   // CE
     PUSH(Reg16),                 // C5 D5 E5 F5
     POP(Reg16),                  // C1 D1 E1 F1
+    RETNC,                       // D0
+    JPNCa16(UnmappedAddress),    // D2
+    CALLNCa16(UnmappedAddress),  // D4
+    SUBd8(u8),                   // D6
+    RETC,                        // D8
+    RETI,                        // D9
+    JPCa16(UnmappedAddress),     // DA
+    CALLCa16(UnmappedAddress),   // DC
+    SBCd8(u8),                   // DE
     LDHa8A(u8),                  // E0
     LDCA,                        // E2
     ANDd8(u8),                   // E6
+    ADDSPr8(i8),                 // E8
     JPHL,                        // E9
     LDa16A(UnmappedAddress),     // EA
+    XORd8(u8),                   // EE
     LDHAa8(u8),                  // F0
+    LDAC,                        // F2
     DI,                          // F3
+    ORd8(u8),                    // F6
+    LDHLSPr8(i8),                // F8
+    LDSPHL,                      // F9
     LDAa16(UnmappedAddress),     // FA
     EI,                          // FB
     CPd8(u8),                    // FE
+    Illegal(u8),                 // D3 DB DD E3 E4 EB EC ED F4 FC FD
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SpecialInstruction {
-    RL(Reg8),   // 12
-    SLA(Reg8),  // 23
-    RES0(Reg8), // 87
+    RLC(Reg8),     // 00 to 07
+    RRC(Reg8),     // 08 to 0F
+    RL(Reg8),      // 10 to 17
+    RR(Reg8),      // 18 to 1F
+    SLA(Reg8),     // 20 to 27
+    SRA(Reg8),     // 28 to 2F
+    SWAP(Reg8),    // 30 to 37
+    SRL(Reg8),     // 38 to 3F
+    BIT(u8, Reg8), // 40 to 7F
+    RES(u8, Reg8), // 80 to BF
+    SET(u8, Reg8), // C0 to FF
 }
 
 impl SpecialInstruction {
     pub fn from_byte(byte: u8) -> Option<SpecialInstruction> {
+        let register = REGISTERS[(byte & 0x07) as usize];
+        let bit = (byte & 0x38) >> 3;
         match byte {
-            0x12 => Some(SpecialInstruction::RL(Reg8::D)),
-            0x23 => Some(SpecialInstruction::SLA(Reg8::E)),
-            0x87 => Some(SpecialInstruction::RES0(Reg8::A)),
-            _ => None,
+            0x00..=0x07 => Some(SpecialInstruction::RLC(register)),
+            0x08..=0x0f => Some(SpecialInstruction::RRC(register)),
+            0x10..=0x17 => Some(SpecialInstruction::RL(register)),
+            0x18..=0x1f => Some(SpecialInstruction::RR(register)),
+            0x20..=0x27 => Some(SpecialInstruction::SLA(register)),
+            0x28..=0x2f => Some(SpecialInstruction::SRA(register)),
+            0x30..=0x37 => Some(SpecialInstruction::SWAP(register)),
+            0x38..=0x3f => Some(SpecialInstruction::SRL(register)),
+            0x40..=0x7f => Some(SpecialInstruction::BIT(bit, register)),
+            0x80..=0xbf => Some(SpecialInstruction::RES(bit, register)),
+            0xc0..=0xff => Some(SpecialInstruction::SET(bit, register)),
         }
     }
 
     pub fn name(&self) -> &str {
         match self {
+            SpecialInstruction::RLC(_) => "RLC",
+            SpecialInstruction::RRC(_) => "RRC",
             SpecialInstruction::RL(_) => "RL",
+            SpecialInstruction::RR(_) => "RR",
             SpecialInstruction::SLA(_) => "SLA",
-            SpecialInstruction::RES0(_) => "RES 0",
+            SpecialInstruction::SRA(_) => "SRA",
+            SpecialInstruction::SWAP(_) => "SWAP",
+            SpecialInstruction::SRL(_) => "SRL",
+            SpecialInstruction::BIT(_, _) => "BIT",
+            SpecialInstruction::RES(_, _) => "RES",
+            SpecialInstruction::SET(_, _) => "SET",
         }
     }
 
     pub fn first_argument(&self) -> Option<Argument> {
         match self {
-            &SpecialInstruction::RL(reg)
+            &SpecialInstruction::RLC(reg)
+            | &SpecialInstruction::RRC(reg)
+            | &SpecialInstruction::RL(reg)
+            | &SpecialInstruction::RR(reg)
             | &SpecialInstruction::SLA(reg)
-            | &SpecialInstruction::RES0(reg) => Some(Argument::Reg8(reg)),
+            | &SpecialInstruction::SRA(reg)
+            | &SpecialInstruction::SWAP(reg)
+            | &SpecialInstruction::SRL(reg) => Some(Argument::Reg8(reg)),
+            &SpecialInstruction::BIT(bit, _)
+            | &SpecialInstruction::RES(bit, _)
+            | &SpecialInstruction::SET(bit, _) => Some(Argument::BitIndex(bit)),
         }
     }
+
+    pub fn second_argument(&self) -> Option<Argument> {
+        match self {
+            &SpecialInstruction::BIT(_, reg)
+            | &SpecialInstruction::RES(_, reg)
+            | &SpecialInstruction::SET(_, reg) => Some(Argument::Reg8(reg)),
+            _ => None,
+        }
+    }
+
+    pub fn flags_affected(&self) -> FlagEffects {
+        FlagEffects::from_table(match self {
+            SpecialInstruction::RLC(_)
+            | SpecialInstruction::RRC(_)
+            | SpecialInstruction::RL(_)
+            | SpecialInstruction::RR(_)
+            | SpecialInstruction::SLA(_)
+            | SpecialInstruction::SRA(_)
+            | SpecialInstruction::SRL(_) => "Z00C",
+            SpecialInstruction::SWAP(_) => "Z000",
+            SpecialInstruction::BIT(_, _) => "Z01-",
+            SpecialInstruction::RES(_, _) | SpecialInstruction::SET(_, _) => "----",
+        })
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -116,6 +519,18 @@ impl fmt::Display for Reg8 {
     }
 }
 
+/// 8-bit registers in the order they are encoded in the low three bits of an opcode
+const REGISTERS: [Reg8; 8] = [
+    Reg8::B,
+    Reg8::C,
+    Reg8::D,
+    Reg8::E,
+    Reg8::H,
+    Reg8::L,
+    Reg8::IndirectHL,
+    Reg8::A,
+];
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Reg16 {
     AF,
@@ -187,6 +602,92 @@ impl fmt::Display for ResetVector {
     }
 }
 
+/// The flags branch conditions test
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flag {
+    Zero,
+    Carry,
+}
+
+/// What an instruction does to one of the flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlagEffect {
+    Unchanged,
+    Reset,
+    Set,
+    /// Set or reset depending on the result
+    Affected,
+}
+
+/// What an instruction does to the Z, N, H and C flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FlagEffects {
+    pub zero: FlagEffect,
+    pub subtract: FlagEffect,
+    pub half_carry: FlagEffect,
+    pub carry: FlagEffect,
+}
+
+impl FlagEffects {
+    /// Reads the effects in the notation of the opcode tables, e.g. `Z0H-`: the letter of
+    /// the flag if it depends on the result, `0` or `1` if it is reset or set, `-` if it is
+    /// left as is
+    fn from_table(effects: &str) -> FlagEffects {
+        let mut effects = effects.bytes().map(|effect| match effect {
+            b'0' => FlagEffect::Reset,
+            b'1' => FlagEffect::Set,
+            b'-' => FlagEffect::Unchanged,
+            _ => FlagEffect::Affected,
+        });
+        let mut next = || effects.next().unwrap_or(FlagEffect::Unchanged);
+        FlagEffects {
+            zero: next(),
+            subtract: next(),
+            half_carry: next(),
+            carry: next(),
+        }
+    }
+
+    pub fn get(&self, flag: Flag) -> FlagEffect {
+        match flag {
+            Flag::Zero => self.zero,
+            Flag::Carry => self.carry,
+        }
+    }
+
+    /// Whether no flag is changed
+    pub fn is_none(&self) -> bool {
+        [self.zero, self.subtract, self.half_carry, self.carry]
+            .iter()
+            .all(|&effect| effect == FlagEffect::Unchanged)
+    }
+}
+
+impl fmt::Display for FlagEffects {
+    /// Writes the effects like `Z:Z N:0 H:H C:-`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            ('Z', self.zero),
+            ('N', self.subtract),
+            ('H', self.half_carry),
+            ('C', self.carry),
+        ];
+        for (index, (flag, effect)) in flags.iter().enumerate() {
+            let effect = match effect {
+                FlagEffect::Unchanged => '-',
+                FlagEffect::Reset => '0',
+                FlagEffect::Set => '1',
+                FlagEffect::Affected => *flag,
+            };
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}:{}", flag, effect)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Argument {
     Imm8(u8),
@@ -200,10 +701,28 @@ pub enum Argument {
     IndirectHLdec,
     IndirectC,
     ResetVector(ResetVector),
+    SignedImm8(i8),
+    SPRel8(i8),
+    BitIndex(u8),
 }
 
 impl GBInstruction {
+    /// Decodes the instruction at the start of `bytes`, returns None if the opcode isn't
+    /// recognized or if `bytes` ends before the end of the instruction.
     pub fn from_bytes(bytes: &[u8]) -> Option<GBInstruction> {
+        // Decode from a padded copy so that operand reads can't go out of bounds
+        let mut padded = [0u8; 3];
+        let length = bytes.len().min(padded.len());
+        padded[..length].copy_from_slice(&bytes[..length]);
+
+        let instruction = Self::decode(&padded)?;
+        if instruction.size() > bytes.len() {
+            return None;
+        }
+        Some(instruction)
+    }
+
+    fn decode(bytes: &[u8; 3]) -> Option<GBInstruction> {
         match bytes[0] {
             0x00 => Some(GBInstruction::NOP),
             0x01 => {
@@ -215,10 +734,19 @@ impl GBInstruction {
             0x04 => Some(GBInstruction::INC8(Reg8::B)),
             0x05 => Some(GBInstruction::DEC8(Reg8::B)),
             0x06 => Some(GBInstruction::LDd8(Reg8::B, bytes[1])),
+            0x07 => Some(GBInstruction::RLCA),
+            0x08 => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::LDa16SP(UnmappedAddress(address)))
+            }
+            0x09 => Some(GBInstruction::ADDHL(Reg16::BC)),
+            0x0a => Some(GBInstruction::LDAi16(Reg16::BC)),
             0x0b => Some(GBInstruction::DEC16(Reg16::BC)),
             0x0c => Some(GBInstruction::INC8(Reg8::C)),
             0x0d => Some(GBInstruction::DEC8(Reg8::C)),
             0x0e => Some(GBInstruction::LDd8(Reg8::C, bytes[1])),
+            0x0f => Some(GBInstruction::RRCA),
+            0x10 => Some(GBInstruction::STOP),
             0x11 => {
                 let value = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::LDd16(Reg16::DE, value))
@@ -228,11 +756,14 @@ impl GBInstruction {
             0x14 => Some(GBInstruction::INC8(Reg8::D)),
             0x15 => Some(GBInstruction::DEC8(Reg8::D)),
             0x16 => Some(GBInstruction::LDd8(Reg8::D, bytes[1])),
+            0x17 => Some(GBInstruction::RLA),
             0x19 => Some(GBInstruction::ADDHL(Reg16::DE)),
+            0x1a => Some(GBInstruction::LDAi16(Reg16::DE)),
             0x1b => Some(GBInstruction::DEC16(Reg16::DE)),
             0x1c => Some(GBInstruction::INC8(Reg8::E)),
             0x1d => Some(GBInstruction::DEC8(Reg8::E)),
             0x1e => Some(GBInstruction::LDd8(Reg8::E, bytes[1])),
+            0x1f => Some(GBInstruction::RRA),
             0x18 => Some(GBInstruction::JRr8(bytes[1] as i8)),
             0x20 => Some(GBInstruction::JRNZr8(bytes[1] as i8)),
             0x21 => {
@@ -244,12 +775,16 @@ impl GBInstruction {
             0x24 => Some(GBInstruction::INC8(Reg8::H)),
             0x25 => Some(GBInstruction::DEC8(Reg8::H)),
             0x26 => Some(GBInstruction::LDd8(Reg8::H, bytes[1])),
+            0x27 => Some(GBInstruction::DAA),
             0x28 => Some(GBInstruction::JRZr8(bytes[1] as i8)),
-            0x2a => Some(GBInstruction::LDAHLdec),
+            0x29 => Some(GBInstruction::ADDHL(Reg16::HL)),
+            0x2a => Some(GBInstruction::LDAHLinc),
             0x2b => Some(GBInstruction::DEC16(Reg16::HL)),
             0x2c => Some(GBInstruction::INC8(Reg8::L)),
             0x2d => Some(GBInstruction::DEC8(Reg8::L)),
             0x2e => Some(GBInstruction::LDd8(Reg8::L, bytes[1])),
+            0x2f => Some(GBInstruction::CPL),
+            0x30 => Some(GBInstruction::JRNCr8(bytes[1] as i8)),
             0x31 => {
                 let value = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::LDd16(Reg16::SP, value))
@@ -259,31 +794,25 @@ impl GBInstruction {
             0x34 => Some(GBInstruction::INC8(Reg8::IndirectHL)),
             0x35 => Some(GBInstruction::DEC8(Reg8::IndirectHL)),
             0x36 => Some(GBInstruction::LDd8(Reg8::IndirectHL, bytes[1])),
+            0x37 => Some(GBInstruction::SCF),
+            0x38 => Some(GBInstruction::JRCr8(bytes[1] as i8)),
+            0x39 => Some(GBInstruction::ADDHL(Reg16::SP)),
+            0x3a => Some(GBInstruction::LDAHLdec),
             0x3b => Some(GBInstruction::DEC16(Reg16::SP)),
             0x3c => Some(GBInstruction::INC8(Reg8::A)),
             0x3d => Some(GBInstruction::DEC8(Reg8::A)),
             0x3e => Some(GBInstruction::LDd8(Reg8::A, bytes[1])),
+            0x3f => Some(GBInstruction::CCF),
+            0x76 => Some(GBInstruction::HALT),
             0x40..=0x7f => {
-                if bytes[0] == 0x76 {
-                    // TODO
-                    return None;
-                }
-                let registers = [
-                    Reg8::B,
-                    Reg8::C,
-                    Reg8::D,
-                    Reg8::E,
-                    Reg8::H,
-                    Reg8::L,
-                    Reg8::IndirectHL,
-                    Reg8::A,
-                ];
                 let dest_register_index = (bytes[0] & 0x38) >> 3;
                 let source_register_index = bytes[0] & 0x07;
-                let dest_register = registers[dest_register_index as usize];
-                let source_register = registers[source_register_index as usize];
+                let dest_register = REGISTERS[dest_register_index as usize];
+                let source_register = REGISTERS[source_register_index as usize];
                 Some(GBInstruction::LD(dest_register, source_register))
             }
+            0x80..=0x87 => Some(GBInstruction::ADD(REGISTERS[(bytes[0] & 0x07) as usize])),
+            0x88..=0x8f => Some(GBInstruction::ADC(REGISTERS[(bytes[0] & 0x07) as usize])),
             0x90 => Some(GBInstruction::SUB(Reg8::B)),
             0x91 => Some(GBInstruction::SUB(Reg8::C)),
             0x92 => Some(GBInstruction::SUB(Reg8::D)),
@@ -292,6 +821,7 @@ impl GBInstruction {
             0x95 => Some(GBInstruction::SUB(Reg8::L)),
             0x96 => Some(GBInstruction::SUB(Reg8::IndirectHL)),
             0x97 => Some(GBInstruction::SUB(Reg8::A)),
+            0x98..=0x9f => Some(GBInstruction::SBC(REGISTERS[(bytes[0] & 0x07) as usize])),
             0xa0 => Some(GBInstruction::AND(Reg8::B)),
             0xa1 => Some(GBInstruction::AND(Reg8::C)),
             0xa2 => Some(GBInstruction::AND(Reg8::D)),
@@ -334,22 +864,54 @@ impl GBInstruction {
                 let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::JPa16(UnmappedAddress(address)))
             }
+            0xc4 => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::CALLNZa16(UnmappedAddress(address)))
+            }
             0xc5 => Some(GBInstruction::PUSH(Reg16::BC)),
+            0xc6 => Some(GBInstruction::ADDd8(bytes[1])),
             0xc7 => Some(GBInstruction::RST(ResetVector::H00)),
+            0xc8 => Some(GBInstruction::RETZ),
             0xc9 => Some(GBInstruction::RET),
             0xca => {
                 let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::JPZa16(UnmappedAddress(address)))
             }
             0xcb => SpecialInstruction::from_byte(bytes[1]).map(GBInstruction::Special),
+            0xcc => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::CALLZa16(UnmappedAddress(address)))
+            }
             0xcd => {
                 let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::CALLa16(UnmappedAddress(address)))
             }
+            0xce => Some(GBInstruction::ADCd8(bytes[1])),
             0xcf => Some(GBInstruction::RST(ResetVector::H08)),
+            0xd0 => Some(GBInstruction::RETNC),
             0xd1 => Some(GBInstruction::POP(Reg16::DE)),
+            0xd2 => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::JPNCa16(UnmappedAddress(address)))
+            }
+            0xd4 => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::CALLNCa16(UnmappedAddress(address)))
+            }
             0xd5 => Some(GBInstruction::PUSH(Reg16::DE)),
+            0xd6 => Some(GBInstruction::SUBd8(bytes[1])),
             0xd7 => Some(GBInstruction::RST(ResetVector::H10)),
+            0xd8 => Some(GBInstruction::RETC),
+            0xd9 => Some(GBInstruction::RETI),
+            0xda => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::JPCa16(UnmappedAddress(address)))
+            }
+            0xdc => {
+                let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
+                Some(GBInstruction::CALLCa16(UnmappedAddress(address)))
+            }
+            0xde => Some(GBInstruction::SBCd8(bytes[1])),
             0xdf => Some(GBInstruction::RST(ResetVector::H18)),
             0xe0 => Some(GBInstruction::LDHa8A(bytes[1])),
             0xe1 => Some(GBInstruction::POP(Reg16::HL)),
@@ -357,17 +919,23 @@ impl GBInstruction {
             0xe2 => Some(GBInstruction::LDCA),
             0xe5 => Some(GBInstruction::PUSH(Reg16::HL)),
             0xe6 => Some(GBInstruction::ANDd8(bytes[1])),
+            0xe8 => Some(GBInstruction::ADDSPr8(bytes[1] as i8)),
             0xe9 => Some(GBInstruction::JPHL),
             0xea => {
                 let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::LDa16A(UnmappedAddress(address)))
             }
+            0xee => Some(GBInstruction::XORd8(bytes[1])),
             0xef => Some(GBInstruction::RST(ResetVector::H28)),
             0xf0 => Some(GBInstruction::LDHAa8(bytes[1])),
             0xf1 => Some(GBInstruction::POP(Reg16::AF)),
+            0xf2 => Some(GBInstruction::LDAC),
             0xf3 => Some(GBInstruction::DI),
             0xf5 => Some(GBInstruction::PUSH(Reg16::AF)),
+            0xf6 => Some(GBInstruction::ORd8(bytes[1])),
             0xf7 => Some(GBInstruction::RST(ResetVector::H30)),
+            0xf8 => Some(GBInstruction::LDHLSPr8(bytes[1] as i8)),
+            0xf9 => Some(GBInstruction::LDSPHL),
             0xfa => {
                 let address = u16::from_le_bytes(bytes[1..3].try_into().unwrap());
                 Some(GBInstruction::LDAa16(UnmappedAddress(address)))
@@ -375,65 +943,128 @@ impl GBInstruction {
             0xfb => Some(GBInstruction::EI),
             0xfe => Some(GBInstruction::CPd8(bytes[1])),
             0xff => Some(GBInstruction::RST(ResetVector::H38)),
-            _ => None,
+            0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
+                Some(GBInstruction::Illegal(bytes[0]))
+            }
         }
     }
 
     pub fn name(&self) -> &str {
         match self {
             GBInstruction::NOP => "NOP",
+            GBInstruction::RLCA => "RLCA",
+            GBInstruction::RRCA => "RRCA",
+            GBInstruction::LDa16SP(_) => "LD",
+            GBInstruction::STOP => "STOP",
+            GBInstruction::RLA => "RLA",
+            GBInstruction::RRA => "RRA",
+            GBInstruction::DAA => "DAA",
+            GBInstruction::CPL => "CPL",
+            GBInstruction::SCF => "SCF",
+            GBInstruction::CCF => "CCF",
             GBInstruction::LDd16(_, _)
             | GBInstruction::LDd8(_, _)
             | GBInstruction::LDi16A(_)
+            | GBInstruction::LDAi16(_)
             | GBInstruction::LDHLincA
+            | GBInstruction::LDAHLinc
             | GBInstruction::LDAHLdec
             | GBInstruction::LDHLdecA
             | GBInstruction::LD(_, _)
             | GBInstruction::LDa16A(_)
             | GBInstruction::LDAa16(_)
-            | GBInstruction::LDCA => "LD",
+            | GBInstruction::LDCA
+            | GBInstruction::LDAC
+            | GBInstruction::LDHLSPr8(_)
+            | GBInstruction::LDSPHL => "LD",
             GBInstruction::LDHAa8(_) | GBInstruction::LDHa8A(_) => "LDH",
             GBInstruction::DEC8(_) | GBInstruction::DEC16(_) => "DEC",
-            GBInstruction::ADDHL(_) => "ADD",
+            GBInstruction::ADDHL(_)
+            | GBInstruction::ADD(_)
+            | GBInstruction::ADDd8(_)
+            | GBInstruction::ADDSPr8(_) => "ADD",
+            GBInstruction::ADC(_) | GBInstruction::ADCd8(_) => "ADC",
+            GBInstruction::SBC(_) | GBInstruction::SBCd8(_) => "SBC",
             GBInstruction::JRr8(_) => "JR",
             GBInstruction::JRNZr8(_) => "JR NZ",
             GBInstruction::JRZr8(_) => "JR Z",
+            GBInstruction::JRNCr8(_) => "JR NC",
+            GBInstruction::JRCr8(_) => "JR C",
             GBInstruction::INC8(_) | GBInstruction::INC16(_) => "INC",
-            GBInstruction::XOR(_) => "XOR",
-            GBInstruction::OR(_) => "OR",
+            GBInstruction::XOR(_) | GBInstruction::XORd8(_) => "XOR",
+            GBInstruction::OR(_) | GBInstruction::ORd8(_) => "OR",
             GBInstruction::CP(_) => "CP",
             GBInstruction::RETNZ => "RET NZ",
+            GBInstruction::RETZ => "RET Z",
+            GBInstruction::RETNC => "RET NC",
+            GBInstruction::RETC => "RET C",
             GBInstruction::JPNZa16(_) => "JP NZ",
             GBInstruction::JPa16(_) | GBInstruction::JPHL => "JP",
             GBInstruction::RET => "RET",
+            GBInstruction::RETI => "RETI",
             GBInstruction::JPZa16(_) => "JP Z",
+            GBInstruction::JPNCa16(_) => "JP NC",
+            GBInstruction::JPCa16(_) => "JP C",
             GBInstruction::Special(special_instruction) => special_instruction.name(),
             GBInstruction::CALLa16(_) => "CALL",
+            GBInstruction::CALLNZa16(_) => "CALL NZ",
+            GBInstruction::CALLZa16(_) => "CALL Z",
+            GBInstruction::CALLNCa16(_) => "CALL NC",
+            GBInstruction::CALLCa16(_) => "CALL C",
             GBInstruction::PUSH(_) => "PUSH",
             GBInstruction::POP(_) => "POP",
-            GBInstruction::SUB(_) => "SUB",
+            GBInstruction::SUB(_) | GBInstruction::SUBd8(_) => "SUB",
             GBInstruction::AND(_) | GBInstruction::ANDd8(_) => "AND",
+            GBInstruction::HALT => "HALT",
             GBInstruction::DI => "DI",
             GBInstruction::EI => "EI",
             GBInstruction::CPd8(_) => "CP",
             GBInstruction::RST(_) => "RST",
+            GBInstruction::Illegal(_) => "ILLEGAL",
         }
     }
 
     pub fn first_argument(&self) -> Option<Argument> {
         match self {
-            GBInstruction::NOP | GBInstruction::DI | GBInstruction::EI => None,
+            GBInstruction::NOP
+            | GBInstruction::HALT
+            | GBInstruction::STOP
+            | GBInstruction::DI
+            | GBInstruction::EI
+            | GBInstruction::RLCA
+            | GBInstruction::RRCA
+            | GBInstruction::RLA
+            | GBInstruction::RRA
+            | GBInstruction::DAA
+            | GBInstruction::CPL
+            | GBInstruction::SCF
+            | GBInstruction::CCF
+            | GBInstruction::Illegal(_) => None,
             GBInstruction::LDd16(reg, _)
             | GBInstruction::INC16(reg)
             | GBInstruction::DEC16(reg)
             | GBInstruction::PUSH(reg)
             | GBInstruction::POP(reg) => Some(Argument::Reg16(*reg)),
-            GBInstruction::ADDHL(_) => Some(Argument::Reg16(Reg16::HL)),
+            GBInstruction::ADDHL(_) | GBInstruction::LDHLSPr8(_) => {
+                Some(Argument::Reg16(Reg16::HL))
+            }
+            GBInstruction::ADDSPr8(_) | GBInstruction::LDSPHL => Some(Argument::Reg16(Reg16::SP)),
+            GBInstruction::ADD(_)
+            | GBInstruction::ADC(_)
+            | GBInstruction::SBC(_)
+            | GBInstruction::ADDd8(_)
+            | GBInstruction::ADCd8(_)
+            | GBInstruction::SBCd8(_) => Some(Argument::Reg8(Reg8::A)),
             GBInstruction::JRr8(value)
             | GBInstruction::JRNZr8(value)
-            | GBInstruction::JRZr8(value) => Some(Argument::Rel8(*value)),
+            | GBInstruction::JRZr8(value)
+            | GBInstruction::JRNCr8(value)
+            | GBInstruction::JRCr8(value) => Some(Argument::Rel8(*value)),
             GBInstruction::LDHLincA => Some(Argument::IndirectHLinc),
-            GBInstruction::LDAHLdec => Some(Argument::Reg8(Reg8::A)),
+            GBInstruction::LDAHLinc
+            | GBInstruction::LDAHLdec
+            | GBInstruction::LDAi16(_)
+            | GBInstruction::LDAC => Some(Argument::Reg8(Reg8::A)),
             GBInstruction::LDHLdecA => Some(Argument::IndirectHLdec),
             GBInstruction::LDd8(reg, _)
             | GBInstruction::LD(reg, _)
@@ -445,18 +1076,32 @@ impl GBInstruction {
             | GBInstruction::OR(reg)
             | GBInstruction::CP(reg) => Some(Argument::Reg8(*reg)),
             GBInstruction::LDa16A(address)
+            | GBInstruction::LDa16SP(address)
             | GBInstruction::JPa16(address)
             | GBInstruction::JPZa16(address)
             | GBInstruction::JPNZa16(address)
-            | GBInstruction::CALLa16(address) => Some(Argument::Address(*address)),
-            GBInstruction::RET | GBInstruction::RETNZ => None,
+            | GBInstruction::JPCa16(address)
+            | GBInstruction::JPNCa16(address)
+            | GBInstruction::CALLa16(address)
+            | GBInstruction::CALLNZa16(address)
+            | GBInstruction::CALLZa16(address)
+            | GBInstruction::CALLNCa16(address)
+            | GBInstruction::CALLCa16(address) => Some(Argument::Address(*address)),
+            GBInstruction::RET
+            | GBInstruction::RETI
+            | GBInstruction::RETNZ
+            | GBInstruction::RETZ
+            | GBInstruction::RETNC
+            | GBInstruction::RETC => None,
             GBInstruction::Special(special_instruction) => special_instruction.first_argument(),
             GBInstruction::LDHa8A(value) => {
                 Some(Argument::Address(UnmappedAddress(0xff00 | (*value as u16))))
             }
-            GBInstruction::CPd8(value) | GBInstruction::ANDd8(value) => {
-                Some(Argument::Imm8(*value))
-            }
+            GBInstruction::CPd8(value)
+            | GBInstruction::ANDd8(value)
+            | GBInstruction::SUBd8(value)
+            | GBInstruction::XORd8(value)
+            | GBInstruction::ORd8(value) => Some(Argument::Imm8(*value)),
             GBInstruction::JPHL => Some(Argument::IndirectReg16(Reg16::HL)),
             GBInstruction::LDHAa8(_) | GBInstruction::LDAa16(_) => Some(Argument::Reg8(Reg8::A)),
             GBInstruction::LDCA => Some(Argument::IndirectC),
@@ -474,6 +1119,8 @@ impl GBInstruction {
             | GBInstruction::JRr8(_)
             | GBInstruction::JRZr8(_)
             | GBInstruction::JRNZr8(_)
+            | GBInstruction::JRNCr8(_)
+            | GBInstruction::JRCr8(_)
             | GBInstruction::SUB(_)
             | GBInstruction::AND(_)
             | GBInstruction::XOR(_)
@@ -481,45 +1128,163 @@ impl GBInstruction {
             | GBInstruction::CP(_)
             | GBInstruction::ANDd8(_)
             | GBInstruction::CPd8(_)
+            | GBInstruction::SUBd8(_)
+            | GBInstruction::XORd8(_)
+            | GBInstruction::ORd8(_)
             | GBInstruction::JPNZa16(_)
             | GBInstruction::JPa16(_)
             | GBInstruction::JPHL
             | GBInstruction::RET
+            | GBInstruction::RETI
             | GBInstruction::JPZa16(_)
-            | GBInstruction::Special(_)
+            | GBInstruction::JPCa16(_)
+            | GBInstruction::JPNCa16(_)
             | GBInstruction::CALLa16(_)
+            | GBInstruction::CALLNZa16(_)
+            | GBInstruction::CALLZa16(_)
+            | GBInstruction::CALLNCa16(_)
+            | GBInstruction::CALLCa16(_)
             | GBInstruction::PUSH(_)
             | GBInstruction::RETNZ
+            | GBInstruction::RETZ
+            | GBInstruction::RETNC
+            | GBInstruction::RETC
             | GBInstruction::POP(_)
             | GBInstruction::NOP
+            | GBInstruction::RLCA
+            | GBInstruction::RRCA
+            | GBInstruction::RLA
+            | GBInstruction::RRA
+            | GBInstruction::DAA
+            | GBInstruction::CPL
+            | GBInstruction::SCF
+            | GBInstruction::CCF
+            | GBInstruction::HALT
+            | GBInstruction::STOP
             | GBInstruction::DI
             | GBInstruction::EI
-            | GBInstruction::RST(_) => None,
+            | GBInstruction::RST(_)
+            | GBInstruction::Illegal(_) => None,
+            GBInstruction::Special(special_instruction) => special_instruction.second_argument(),
             GBInstruction::LDd16(_, value) => Some(Argument::Imm16(*value)),
-            GBInstruction::LDd8(_, value) => Some(Argument::Imm8(*value)),
+            GBInstruction::LDd8(_, value)
+            | GBInstruction::ADDd8(value)
+            | GBInstruction::ADCd8(value)
+            | GBInstruction::SBCd8(value) => Some(Argument::Imm8(*value)),
             GBInstruction::ADDHL(reg) => Some(Argument::Reg16(*reg)),
+            GBInstruction::ADDSPr8(value) => Some(Argument::SignedImm8(*value)),
+            GBInstruction::LDHLSPr8(value) => Some(Argument::SPRel8(*value)),
+            GBInstruction::LDSPHL => Some(Argument::Reg16(Reg16::HL)),
+            GBInstruction::LDa16SP(_) => Some(Argument::Reg16(Reg16::SP)),
             GBInstruction::LDa16A(_)
             | GBInstruction::LDHa8A(_)
             | GBInstruction::LDHLincA
             | GBInstruction::LDHLdecA
             | GBInstruction::LDCA
             | GBInstruction::LDi16A(_) => Some(Argument::Reg8(Reg8::A)),
-            GBInstruction::LD(_, reg) => Some(Argument::Reg8(*reg)),
+            GBInstruction::LD(_, reg)
+            | GBInstruction::ADD(reg)
+            | GBInstruction::ADC(reg)
+            | GBInstruction::SBC(reg) => Some(Argument::Reg8(*reg)),
             GBInstruction::LDHAa8(value) => {
                 Some(Argument::Address(UnmappedAddress(0xff00 | (*value as u16))))
             }
+            GBInstruction::LDAHLinc => Some(Argument::IndirectHLinc),
             GBInstruction::LDAHLdec => Some(Argument::IndirectHLdec),
+            GBInstruction::LDAi16(reg) => Some(Argument::IndirectReg16(*reg)),
+            GBInstruction::LDAC => Some(Argument::IndirectC),
             GBInstruction::LDAa16(address) => Some(Argument::Address(*address)),
         }
     }
 
+    /// Returns what the instruction does to the flags
+    pub fn flags_affected(&self) -> FlagEffects {
+        FlagEffects::from_table(match self {
+            GBInstruction::INC8(_) => "Z0H-",
+            GBInstruction::DEC8(_) => "Z1H-",
+            GBInstruction::RLCA | GBInstruction::RRCA | GBInstruction::RLA | GBInstruction::RRA => {
+                "000C"
+            }
+            GBInstruction::ADDHL(_) => "-0HC",
+            GBInstruction::DAA => "Z-0C",
+            GBInstruction::CPL => "-11-",
+            GBInstruction::SCF => "-001",
+            GBInstruction::CCF => "-00C",
+            GBInstruction::ADD(_)
+            | GBInstruction::ADC(_)
+            | GBInstruction::ADDd8(_)
+            | GBInstruction::ADCd8(_) => "Z0HC",
+            GBInstruction::SUB(_)
+            | GBInstruction::SBC(_)
+            | GBInstruction::CP(_)
+            | GBInstruction::SUBd8(_)
+            | GBInstruction::SBCd8(_)
+            | GBInstruction::CPd8(_) => "Z1HC",
+            GBInstruction::AND(_) | GBInstruction::ANDd8(_) => "Z010",
+            GBInstruction::XOR(_)
+            | GBInstruction::OR(_)
+            | GBInstruction::XORd8(_)
+            | GBInstruction::ORd8(_) => "Z000",
+            GBInstruction::ADDSPr8(_) | GBInstruction::LDHLSPr8(_) => "00HC",
+            // The flags are the low byte of AF
+            GBInstruction::POP(Reg16::AF) => "ZNHC",
+            GBInstruction::Special(special_instruction) => {
+                return special_instruction.flags_affected()
+            }
+            _ => "----",
+        })
+    }
+
+    /// Returns the flag the condition of a conditional branch tests
+    pub fn tested_flag(&self) -> Option<Flag> {
+        match self {
+            GBInstruction::JRNZr8(_)
+            | GBInstruction::JRZr8(_)
+            | GBInstruction::JPNZa16(_)
+            | GBInstruction::JPZa16(_)
+            | GBInstruction::CALLNZa16(_)
+            | GBInstruction::CALLZa16(_)
+            | GBInstruction::RETNZ
+            | GBInstruction::RETZ => Some(Flag::Zero),
+            GBInstruction::JRNCr8(_)
+            | GBInstruction::JRCr8(_)
+            | GBInstruction::JPNCa16(_)
+            | GBInstruction::JPCa16(_)
+            | GBInstruction::CALLNCa16(_)
+            | GBInstruction::CALLCa16(_)
+            | GBInstruction::RETNC
+            | GBInstruction::RETC => Some(Flag::Carry),
+            _ => None,
+        }
+    }
+
+    /// Returns the address of the memory the instruction loads from or stores to, if it is a
+    /// fixed one
+    pub fn memory_operand(&self) -> Option<UnmappedAddress> {
+        match *self {
+            GBInstruction::LDAa16(address)
+            | GBInstruction::LDa16A(address)
+            | GBInstruction::LDa16SP(address) => Some(address),
+            GBInstruction::LDHAa8(offset) | GBInstruction::LDHa8A(offset) => {
+                Some(UnmappedAddress(0xff00 + offset as u16))
+            }
+            _ => None,
+        }
+    }
+
     /// Returns the jump address if this instruction contains one.
     pub fn jump_address(&self) -> Option<UnmappedAddress> {
         match self {
             &GBInstruction::JPa16(address)
             | &GBInstruction::CALLa16(address)
+            | &GBInstruction::CALLNZa16(address)
+            | &GBInstruction::CALLZa16(address)
+            | &GBInstruction::CALLNCa16(address)
+            | &GBInstruction::CALLCa16(address)
             | &GBInstruction::JPZa16(address)
-            | &GBInstruction::JPNZa16(address) => Some(address),
+            | &GBInstruction::JPNZa16(address)
+            | &GBInstruction::JPCa16(address)
+            | &GBInstruction::JPNCa16(address) => Some(address),
             &GBInstruction::RST(reset_vector) => Some(reset_vector.address()),
             _ => None,
         }
@@ -529,6 +1294,9 @@ impl GBInstruction {
 pub struct GameBoy;
 
 impl Architecture for GameBoy {
+    const NAME: &'static str = "gb";
+    const MAX_INSTRUCTION_SIZE: usize = 3;
+
     type Instruction = self::GBInstruction;
 
     fn disassemble(bytes: &[u8]) -> Option<GBInstruction> {
@@ -538,24 +1306,27 @@ impl Architecture for GameBoy {
     fn resolve_address(
         address: LogicalAddress,
         location: usize,
-        _state: &crate::disassembler::DisassemblerState,
+        state: &crate::disassembler::DisassemblerState,
     ) -> Option<usize> {
         match address {
             LogicalAddress::Absolute(address) => {
-                if address < 0x4000 {
-                    Some(address)
-                } else if address < 0x8000 {
-                    // TODO
-                    None
-                } else {
-                    // TODO
-                    None
-                }
-            }
-            LogicalAddress::Relative(offset) => {
-                let absolute_address = location.wrapping_add(offset as usize);
-                Some(absolute_address)
+                let address = UnmappedAddress(address.try_into().ok()?);
+                resolve(location, address, state.bank(location), state.rom().len()).get()
             }
+            LogicalAddress::Relative(offset) => location
+                .checked_add_signed(offset)
+                .filter(|&address| address < state.rom().len()),
+        }
+    }
+
+    /// The vectors are tiny, a handler longer than its slot usually jumps elsewhere rather
+    /// than running into the next one, and the entry point must jump over the header. Falling
+    /// through into them would mark the other handlers or the Nintendo logo as code.
+    fn stops_fall_through(address: usize) -> bool {
+        match address {
+            0..=VECTORS_END => address.is_multiple_of(VECTOR_SIZE),
+            HEADER_START => true,
+            _ => false,
         }
     }
 }
@@ -564,8 +1335,19 @@ impl Instruction for GBInstruction {
     fn size(&self) -> usize {
         match self {
             GBInstruction::NOP => 1,
+            GBInstruction::RLCA => 1,
+            GBInstruction::LDa16SP(_) => 3,
+            GBInstruction::RRCA => 1,
+            GBInstruction::STOP => 2,
+            GBInstruction::RLA => 1,
+            GBInstruction::RRA => 1,
+            GBInstruction::DAA => 1,
+            GBInstruction::CPL => 1,
+            GBInstruction::SCF => 1,
+            GBInstruction::CCF => 1,
             GBInstruction::LDd16(_, _) => 3,
             GBInstruction::LDi16A(_) => 1,
+            GBInstruction::LDAi16(_) => 1,
             GBInstruction::INC8(_) => 1,
             GBInstruction::DEC8(_) => 1,
             GBInstruction::INC16(_) => 1,
@@ -575,11 +1357,18 @@ impl Instruction for GBInstruction {
             GBInstruction::ADDHL(_) => 1,
             GBInstruction::JRr8(_) => 2,
             GBInstruction::JRNZr8(_) => 2,
+            GBInstruction::JRNCr8(_) => 2,
+            GBInstruction::JRCr8(_) => 2,
             GBInstruction::LDHLincA => 1,
+            GBInstruction::LDAHLinc => 1,
             GBInstruction::LDAHLdec => 1,
             GBInstruction::LDHLdecA => 1,
             GBInstruction::LD(_, _) => 1,
+            GBInstruction::HALT => 1,
+            GBInstruction::ADD(_) => 1,
+            GBInstruction::ADC(_) => 1,
             GBInstruction::SUB(_) => 1,
+            GBInstruction::SBC(_) => 1,
             GBInstruction::AND(_) => 1,
             GBInstruction::XOR(_) => 1,
             GBInstruction::OR(_) => 1,
@@ -587,15 +1376,32 @@ impl Instruction for GBInstruction {
             GBInstruction::JPNZa16(_) => 3,
             GBInstruction::JPa16(_) => 3,
             GBInstruction::RET => 1,
+            GBInstruction::RETI => 1,
             GBInstruction::JPZa16(_) => 3,
+            GBInstruction::JPNCa16(_) => 3,
+            GBInstruction::JPCa16(_) => 3,
             GBInstruction::Special(_) => 2,
             GBInstruction::CALLa16(_) => 3,
+            GBInstruction::CALLNZa16(_) => 3,
+            GBInstruction::CALLZa16(_) => 3,
+            GBInstruction::CALLNCa16(_) => 3,
+            GBInstruction::CALLCa16(_) => 3,
             GBInstruction::PUSH(_) => 1,
             GBInstruction::RETNZ => 1,
+            GBInstruction::RETZ => 1,
+            GBInstruction::RETNC => 1,
+            GBInstruction::RETC => 1,
             GBInstruction::POP(_) => 1,
             GBInstruction::LDHa8A(_) => 2,
             GBInstruction::LDCA => 1,
+            GBInstruction::LDAC => 1,
             GBInstruction::ANDd8(_) => 2,
+            GBInstruction::ADDd8(_) => 2,
+            GBInstruction::ADCd8(_) => 2,
+            GBInstruction::SUBd8(_) => 2,
+            GBInstruction::SBCd8(_) => 2,
+            GBInstruction::XORd8(_) => 2,
+            GBInstruction::ORd8(_) => 2,
             GBInstruction::JPHL => 1,
             GBInstruction::LDa16A(_) => 3,
             GBInstruction::DI => 1,
@@ -603,33 +1409,47 @@ impl Instruction for GBInstruction {
             GBInstruction::LDHAa8(_) => 2,
             GBInstruction::EI => 1,
             GBInstruction::CPd8(_) => 2,
+            GBInstruction::ADDSPr8(_) => 2,
+            GBInstruction::LDHLSPr8(_) => 2,
+            GBInstruction::LDSPHL => 1,
             GBInstruction::RST(_) => 1,
+            GBInstruction::Illegal(_) => 1,
         }
     }
 
     /// Returns `true` if the execution can continue past this instruction. This would be false for
     /// unconditional jumps for example.
     fn falls_through(&self) -> bool {
-        match self {
+        !matches!(
+            self,
             GBInstruction::JPa16(_)
-            | GBInstruction::JPHL
-            | GBInstruction::RET
-            | GBInstruction::JRr8(_) => false,
-            _ => true,
-        }
+                | GBInstruction::JPHL
+                | GBInstruction::RET
+                | GBInstruction::RETI
+                | GBInstruction::JRr8(_)
+                | GBInstruction::Illegal(_)
+        )
     }
 
     fn branch_address(&self) -> Option<crate::disassembler::LogicalAddress> {
         match self {
             &GBInstruction::JPa16(address)
             | &GBInstruction::CALLa16(address)
+            | &GBInstruction::CALLNZa16(address)
+            | &GBInstruction::CALLZa16(address)
+            | &GBInstruction::CALLNCa16(address)
+            | &GBInstruction::CALLCa16(address)
             | &GBInstruction::JPZa16(address)
-            | &GBInstruction::JPNZa16(address) => {
+            | &GBInstruction::JPNZa16(address)
+            | &GBInstruction::JPCa16(address)
+            | &GBInstruction::JPNCa16(address) => {
                 Some(LogicalAddress::Absolute(address.0 as usize))
             }
             &GBInstruction::JRr8(offset)
             | &GBInstruction::JRZr8(offset)
-            | &GBInstruction::JRNZr8(offset) => Some(LogicalAddress::Relative(
+            | &GBInstruction::JRNZr8(offset)
+            | &GBInstruction::JRNCr8(offset)
+            | &GBInstruction::JRCr8(offset) => Some(LogicalAddress::Relative(
                 offset as isize + self.size() as isize,
             )),
             &GBInstruction::RST(reset_vector) => {
@@ -638,4 +1458,345 @@ impl Instruction for GBInstruction {
             _ => None,
         }
     }
+
+    fn is_call(&self) -> bool {
+        matches!(
+            self,
+            GBInstruction::CALLa16(_)
+                | GBInstruction::CALLNZa16(_)
+                | GBInstruction::CALLZa16(_)
+                | GBInstruction::CALLNCa16(_)
+                | GBInstruction::CALLCa16(_)
+                | GBInstruction::RST(_)
+        )
+    }
+
+    fn data_address(&self) -> Option<LogicalAddress> {
+        self.memory_operand()
+            .map(|address| LogicalAddress::Absolute(address.0 as usize))
+    }
+
+    fn is_illegal(&self) -> bool {
+        matches!(self, GBInstruction::Illegal(_))
+    }
+
+    fn is_trap(&self) -> bool {
+        *self == GBInstruction::RST(ResetVector::H38)
+    }
+
+    fn cycles(&self) -> Cycles {
+        // Accessing memory through (HL) costs an extra cycle per access
+        let indirect = |reg: &Reg8, extra: usize| {
+            if *reg == Reg8::IndirectHL {
+                extra
+            } else {
+                0
+            }
+        };
+        let cycles = match self {
+            GBInstruction::NOP
+            | GBInstruction::RLCA
+            | GBInstruction::RRCA
+            | GBInstruction::RLA
+            | GBInstruction::RRA
+            | GBInstruction::DAA
+            | GBInstruction::CPL
+            | GBInstruction::SCF
+            | GBInstruction::CCF
+            | GBInstruction::HALT
+            | GBInstruction::STOP
+            | GBInstruction::DI
+            | GBInstruction::EI
+            | GBInstruction::JPHL
+            | GBInstruction::Illegal(_) => 1,
+            GBInstruction::LDd16(_, _) => 3,
+            GBInstruction::LDi16A(_)
+            | GBInstruction::LDAi16(_)
+            | GBInstruction::LDHLincA
+            | GBInstruction::LDAHLinc
+            | GBInstruction::LDHLdecA
+            | GBInstruction::LDAHLdec
+            | GBInstruction::LDCA
+            | GBInstruction::LDAC => 2,
+            GBInstruction::INC16(_) | GBInstruction::DEC16(_) | GBInstruction::ADDHL(_) => 2,
+            GBInstruction::INC8(reg) | GBInstruction::DEC8(reg) => 1 + indirect(reg, 2),
+            GBInstruction::LDd8(reg, _) => 2 + indirect(reg, 1),
+            GBInstruction::LD(destination, source) => {
+                1 + indirect(destination, 1) + indirect(source, 1)
+            }
+            GBInstruction::ADD(reg)
+            | GBInstruction::ADC(reg)
+            | GBInstruction::SUB(reg)
+            | GBInstruction::SBC(reg)
+            | GBInstruction::AND(reg)
+            | GBInstruction::XOR(reg)
+            | GBInstruction::OR(reg)
+            | GBInstruction::CP(reg) => 1 + indirect(reg, 1),
+            GBInstruction::ADDd8(_)
+            | GBInstruction::ADCd8(_)
+            | GBInstruction::SUBd8(_)
+            | GBInstruction::SBCd8(_)
+            | GBInstruction::ANDd8(_)
+            | GBInstruction::XORd8(_)
+            | GBInstruction::ORd8(_)
+            | GBInstruction::CPd8(_) => 2,
+            GBInstruction::JRr8(_) => 3,
+            GBInstruction::JRNZr8(_)
+            | GBInstruction::JRZr8(_)
+            | GBInstruction::JRNCr8(_)
+            | GBInstruction::JRCr8(_) => return Cycles::conditional(2, 3),
+            GBInstruction::JPa16(_) => 4,
+            GBInstruction::JPNZa16(_)
+            | GBInstruction::JPZa16(_)
+            | GBInstruction::JPNCa16(_)
+            | GBInstruction::JPCa16(_) => return Cycles::conditional(3, 4),
+            GBInstruction::CALLa16(_) => 6,
+            GBInstruction::CALLNZa16(_)
+            | GBInstruction::CALLZa16(_)
+            | GBInstruction::CALLNCa16(_)
+            | GBInstruction::CALLCa16(_) => return Cycles::conditional(3, 6),
+            GBInstruction::RET | GBInstruction::RETI => 4,
+            GBInstruction::RETNZ
+            | GBInstruction::RETZ
+            | GBInstruction::RETNC
+            | GBInstruction::RETC => return Cycles::conditional(2, 5),
+            GBInstruction::PUSH(_) | GBInstruction::RST(_) => 4,
+            GBInstruction::POP(_) => 3,
+            GBInstruction::LDHa8A(_) | GBInstruction::LDHAa8(_) => 3,
+            GBInstruction::LDa16A(_) | GBInstruction::LDAa16(_) => 4,
+            GBInstruction::LDa16SP(_) => 5,
+            GBInstruction::ADDSPr8(_) => 4,
+            GBInstruction::LDHLSPr8(_) => 3,
+            GBInstruction::LDSPHL => 2,
+            GBInstruction::Special(special_instruction) => match special_instruction {
+                SpecialInstruction::BIT(_, reg) => 2 + indirect(reg, 1),
+                SpecialInstruction::RLC(reg)
+                | SpecialInstruction::RRC(reg)
+                | SpecialInstruction::RL(reg)
+                | SpecialInstruction::RR(reg)
+                | SpecialInstruction::SLA(reg)
+                | SpecialInstruction::SRA(reg)
+                | SpecialInstruction::SWAP(reg)
+                | SpecialInstruction::SRL(reg)
+                | SpecialInstruction::RES(_, reg)
+                | SpecialInstruction::SET(_, reg) => 2 + indirect(reg, 2),
+            },
+        };
+        Cycles::fixed(cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes_truncated_instruction() {
+        assert_eq!(GBInstruction::from_bytes(&[0xc3]), None);
+        assert_eq!(GBInstruction::from_bytes(&[0xc3, 0x50]), None);
+        assert_eq!(
+            GBInstruction::from_bytes(&[0xc3, 0x50, 0x01]),
+            Some(GBInstruction::JPa16(UnmappedAddress(0x0150)))
+        );
+    }
+
+    #[test]
+    fn from_bytes_empty() {
+        assert_eq!(GBInstruction::from_bytes(&[]), None);
+    }
+
+    #[test]
+    fn memory_regions() {
+        assert_eq!(MemoryRegion::of(0x7fff), None);
+        assert_eq!(MemoryRegion::of(0x8010), Some(MemoryRegion::VRAM));
+        assert_eq!(MemoryRegion::of(0xc0a0), Some(MemoryRegion::WRAM));
+        assert_eq!(MemoryRegion::of(0xfdff), Some(MemoryRegion::EchoRAM));
+        assert_eq!(MemoryRegion::of(0xfe9f), Some(MemoryRegion::OAM));
+        assert_eq!(MemoryRegion::of(0xff44), Some(MemoryRegion::IO));
+        assert_eq!(MemoryRegion::of(0xff80), Some(MemoryRegion::HRAM));
+        assert_eq!(MemoryRegion::of(0xffff), Some(MemoryRegion::IO));
+    }
+
+    /// Size of every opcode from the usual opcode tables, one row per high nibble, 0 for the
+    /// illegal ones
+    const OPCODE_SIZES: [&str; 16] = [
+        "1311112131111121", // 0x
+        "2311112121111121", // 1x
+        "2311112121111121", // 2x
+        "2311112121111121", // 3x
+        "1111111111111111", // 4x
+        "1111111111111111", // 5x
+        "1111111111111111", // 6x
+        "1111111111111111", // 7x
+        "1111111111111111", // 8x
+        "1111111111111111", // 9x
+        "1111111111111111", // ax
+        "1111111111111111", // bx
+        "1133312111323321", // cx
+        "1130312111303021", // dx
+        "2110012121300021", // ex
+        "2111012121310021", // fx
+    ];
+
+    #[test]
+    fn opcode_sizes() {
+        for opcode in 0..=0xffu8 {
+            let expected = OPCODE_SIZES[opcode as usize >> 4].as_bytes()[opcode as usize & 0xf];
+            let expected = (expected - b'0') as usize;
+            // Padded so that every opcode has all of its operands
+            let instruction = GBInstruction::from_bytes(&[opcode, 0x00, 0x00])
+                .unwrap_or_else(|| panic!("{:02x} isn't decoded", opcode));
+            if expected == 0 {
+                assert!(instruction.is_illegal(), "{:02x} should be illegal", opcode);
+                assert_eq!(instruction.size(), 1, "size of illegal {:02x}", opcode);
+            } else {
+                assert!(
+                    !instruction.is_illegal(),
+                    "{:02x} shouldn't be illegal",
+                    opcode
+                );
+                assert_eq!(instruction.size(), expected, "size of {:02x}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn cb_opcode_sizes() {
+        for opcode in 0..=0xffu8 {
+            let instruction = GBInstruction::from_bytes(&[0xcb, opcode])
+                .unwrap_or_else(|| panic!("cb {:02x} isn't decoded", opcode));
+            assert!(
+                !instruction.is_illegal(),
+                "cb {:02x} shouldn't be illegal",
+                opcode
+            );
+            assert_eq!(instruction.size(), 2, "size of cb {:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn entry_jump() {
+        let mut rom = vec![0u8; 0x200];
+        // NOP; JP 0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        let disassembler = Disassembler::<GameBoy>::new(rom.clone());
+        assert_eq!(entry_jump_target(&disassembler), Some(0x150));
+
+        // JP 0150 without the NOP
+        rom[0x100..0x104].copy_from_slice(&[0xc3, 0x50, 0x01, 0x00]);
+        let disassembler = Disassembler::<GameBoy>::new(rom.clone());
+        assert_eq!(entry_jump_target(&disassembler), Some(0x150));
+
+        // Not a jump
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0x3c, 0x50, 0x01]);
+        let disassembler = Disassembler::<GameBoy>::new(rom.clone());
+        assert_eq!(entry_jump_target(&disassembler), None);
+
+        // A jump past the end of the ROM
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x00, 0x30]);
+        let disassembler = Disassembler::<GameBoy>::new(rom);
+        assert_eq!(entry_jump_target(&disassembler), None);
+    }
+
+    #[test]
+    fn analysis_keeps_data_vectors() {
+        // INC A; RET at every vector
+        let mut rom = vec![0u8; 0x200];
+        for vector in (0..0x68).step_by(8) {
+            rom[vector..vector + 2].copy_from_slice(&[0x3c, 0xc9]);
+        }
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.set_byte_type(0x08, ByteType::Data);
+
+        analyze_entry_points(&mut disassembler, None);
+        assert_eq!(disassembler.byte_type(0x08), ByteType::Data);
+        assert_eq!(disassembler.label(0x08), None);
+        assert_eq!(disassembler.byte_type(0x10), ByteType::Code);
+
+        // Unless it is the one asked for
+        analyze_entry_points(&mut disassembler, Some(0x08));
+        assert_eq!(disassembler.byte_type(0x08), ByteType::Code);
+    }
+
+    #[test]
+    fn analysis_and_viewer_resolve_alike() {
+        // Two banks: JP 3000; CALL 4010 (in the bank given below); LD A, (8000); CALL 4010
+        let mut rom = vec![0u8; 2 * BANK_SIZE];
+        rom[..9].copy_from_slice(&[0xc3, 0x00, 0x30, 0xcd, 0x10, 0x40, 0xfa, 0x00, 0x80]);
+        rom[9..12].copy_from_slice(&[0xcd, 0x10, 0x40]);
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        let resolve = |disassembler: &Disassembler<GameBoy>, address: usize| {
+            let target = disassembler.instruction_at(address).unwrap().jump_address();
+            let resolved = resolve_physical_address(disassembler, address, target.unwrap());
+            (resolved.get(), disassembler.branch_target(address))
+        };
+
+        assert_eq!(resolve(&disassembler, 0), (Some(0x3000), Some(0x3000)));
+        assert_eq!(resolve(&disassembler, 3), (None, None));
+        disassembler.set_bank(3, 1);
+        assert_eq!(resolve(&disassembler, 3), (Some(0x4010), Some(0x4010)));
+        // Bank 0 is the ROM without bank switching, as in symbol files and at the goto prompt
+        disassembler.set_bank(3, 0);
+        assert_eq!(resolve(&disassembler, 3), (Some(0x4010), Some(0x4010)));
+        assert_eq!(banked_address(0, 0x4010), Some(0x4010));
+        assert_eq!(banked_address(usize::MAX, 0x4010), None);
+        // The ROM has no bank 3
+        disassembler.set_bank(3, 3);
+        assert_eq!(resolve(&disassembler, 3), (None, None));
+        // The current bank is only for the viewer, the analysis doesn't depend on it
+        assert_eq!(resolve(&disassembler, 9), (None, None));
+        disassembler.set_current_bank(Some(1));
+        assert_eq!(resolve(&disassembler, 9), (Some(0x4010), None));
+        assert!(matches!(
+            resolve_physical_address(&disassembler, 3, UnmappedAddress(0x4010)),
+            ResolvedAddress::PastEnd(0xc010)
+        ));
+        assert!(matches!(
+            resolve_physical_address(&disassembler, 6, UnmappedAddress(0x8000)),
+            ResolvedAddress::System(MemoryRegion::VRAM, 0x8000)
+        ));
+    }
+
+    #[test]
+    fn flag_effects() {
+        let flags = |bytes: &[u8]| {
+            GBInstruction::from_bytes(bytes)
+                .unwrap()
+                .flags_affected()
+                .to_string()
+        };
+        // INC A
+        assert_eq!(flags(&[0x3c]), "Z:Z N:0 H:H C:-");
+        // AND 0f
+        assert_eq!(flags(&[0xe6, 0x0f]), "Z:Z N:0 H:1 C:0");
+        // POP AF
+        assert_eq!(flags(&[0xf1]), "Z:Z N:N H:H C:C");
+        // BIT 7, H
+        assert_eq!(flags(&[0xcb, 0x7c]), "Z:Z N:0 H:1 C:-");
+        // LD A, B
+        assert!(GBInstruction::from_bytes(&[0x78])
+            .unwrap()
+            .flags_affected()
+            .is_none());
+    }
+
+    #[test]
+    fn flag_setter() {
+        // CP 10; LD A, B; JR C, -4; JR Z, -6
+        let rom = vec![0xfe, 0x10, 0x78, 0x38, 0xfb, 0x28, 0xf9];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(0);
+
+        // LD A, B doesn't touch the carry, JR C doesn't either
+        assert_eq!(find_flag_setter(&disassembler, 3), Some(0));
+        assert_eq!(find_flag_setter(&disassembler, 5), Some(0));
+        // Not a conditional branch
+        assert_eq!(find_flag_setter(&disassembler, 2), None);
+
+        // CALL 0000; JR NZ, -5
+        let rom = vec![0xcd, 0x00, 0x00, 0x20, 0xfb];
+        let mut disassembler = Disassembler::<GameBoy>::new(rom);
+        disassembler.mark_code(0);
+        assert_eq!(find_flag_setter(&disassembler, 3), Some(0));
+    }
 }
@@ -0,0 +1,165 @@
+//! Parsing of the cartridge header found at 0x0100-0x014F of every GameBoy ROM.
+
+use crate::gb::{GBInstruction, UnmappedAddress};
+
+const HEADER_END: usize = 0x150;
+
+/// The logo checked by the boot ROM, stored at 0x0104-0x0133
+const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+pub struct RomHeader {
+    /// Where the `JP` at the entry point goes, None if the entry point doesn't start with
+    /// the usual `NOP; JP` or `JP`
+    pub entry_point: Option<UnmappedAddress>,
+    pub logo_valid: bool,
+    pub title: String,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub header_checksum: u8,
+    /// The checksum computed from the header bytes, should match `header_checksum`
+    pub computed_checksum: u8,
+}
+
+impl RomHeader {
+    /// Parses the header of `rom`, returns None if the ROM is too small to have one
+    pub fn parse(rom: &[u8]) -> Option<RomHeader> {
+        if rom.len() < HEADER_END {
+            return None;
+        }
+
+        let entry_point = [0x100, 0x101]
+            .iter()
+            .filter_map(|&address| GBInstruction::from_bytes(&rom[address..HEADER_END]))
+            .find_map(|instruction| match instruction {
+                GBInstruction::JPa16(address) => Some(address),
+                _ => None,
+            });
+
+        let title = rom[0x134..0x144]
+            .iter()
+            .take_while(|&&byte| byte != 0x00)
+            .filter(|byte| (0x20..=0x7e).contains(*byte))
+            .map(|&byte| byte as char)
+            .collect();
+
+        let computed_checksum = rom[0x134..0x14d].iter().fold(0u8, |checksum, &byte| {
+            checksum.wrapping_sub(byte).wrapping_sub(1)
+        });
+
+        Some(RomHeader {
+            entry_point,
+            logo_valid: rom[0x104..0x134] == NINTENDO_LOGO,
+            title,
+            cartridge_type: rom[0x147],
+            rom_size: rom[0x148],
+            ram_size: rom[0x149],
+            header_checksum: rom[0x14d],
+            computed_checksum,
+        })
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "Unknown",
+        }
+    }
+
+    /// The number of 16KiB ROM banks, None if the size byte isn't a known value
+    pub fn rom_banks(&self) -> Option<usize> {
+        if self.rom_size <= 8 {
+            Some(2 << self.rom_size)
+        } else {
+            None
+        }
+    }
+
+    /// The size of the cartridge RAM in KiB, None if the size byte isn't a known value
+    pub fn ram_kib(&self) -> Option<usize> {
+        match self.ram_size {
+            0x00 => Some(0),
+            0x01 => Some(2),
+            0x02 => Some(8),
+            0x03 => Some(32),
+            0x04 => Some(128),
+            0x05 => Some(64),
+            _ => None,
+        }
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_checksum
+    }
+
+    /// Describes the header, one field per line
+    pub fn describe(&self) -> Vec<String> {
+        let entry_point = match self.entry_point {
+            Some(address) => format!("JP {:04x}", address.0),
+            None => "Unknown".to_string(),
+        };
+        let rom_size = match self.rom_banks() {
+            Some(banks) => format!("{} KiB ({} banks)", banks * 16, banks),
+            None => format!("Unknown (${:02x})", self.rom_size),
+        };
+        let ram_size = match self.ram_kib() {
+            Some(size) => format!("{} KiB", size),
+            None => format!("Unknown (${:02x})", self.ram_size),
+        };
+        let checksum = if self.checksum_valid() {
+            format!("{:02x} (valid)", self.header_checksum)
+        } else {
+            format!(
+                "{:02x} (invalid, expected {:02x})",
+                self.header_checksum, self.computed_checksum
+            )
+        };
+        vec![
+            format!("Title:          {}", self.title),
+            format!("Entry point:    {}", entry_point),
+            format!(
+                "Nintendo logo:  {}",
+                if self.logo_valid { "valid" } else { "invalid" }
+            ),
+            format!(
+                "Cartridge type: {} (${:02x})",
+                self.cartridge_type_name(),
+                self.cartridge_type
+            ),
+            format!("ROM size:       {}", rom_size),
+            format!("RAM size:       {}", ram_size),
+            format!("Checksum:       {}", checksum),
+        ]
+    }
+}
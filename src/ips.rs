@@ -0,0 +1,146 @@
+//! Export of the patched bytes as an IPS patch, the usual format ROM hacks are shared in.
+//!
+//! ```text
+//! PATCH
+//! <offset: 3 bytes> <length: 2 bytes> <bytes>
+//! <offset: 3 bytes> 00 00 <length: 2 bytes> <byte>
+//! EOF
+//! ```
+//!
+//! Numbers are big-endian. Records with a length of 0 are runs of a single byte repeated. The
+//! offset of a record can't be the one spelling `EOF`, a record starting there is moved one
+//! byte earlier.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::disassembler::{Architecture, Disassembler};
+
+/// The offset reading as the end of the patch
+const EOF_OFFSET: usize = 0x45_4f46;
+/// The largest offset that fits in the 3 bytes of a record
+const MAX_OFFSET: usize = 0xff_ffff;
+/// The largest length of a record
+const MAX_LENGTH: usize = 0xffff;
+/// The length from which a run of the same byte is stored as a run record, shorter runs take
+/// less space as they are
+const MIN_RUN_LENGTH: usize = 8;
+
+pub fn export<Arch: Architecture>(
+    path: &Path,
+    disassembler: &Disassembler<Arch>,
+) -> io::Result<()> {
+    let patch = encode(
+        disassembler.rom(),
+        disassembler.patches().map(|(address, _)| address),
+    )?;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&patch)?;
+    file.flush()
+}
+
+/// Encodes the bytes of `rom` at the `patched` addresses, in increasing order
+fn encode(rom: &[u8], patched: impl Iterator<Item = usize>) -> io::Result<Vec<u8>> {
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for address in patched {
+        match spans.last_mut() {
+            Some((_, end)) if *end == address => *end += 1,
+            _ => spans.push((address, address + 1)),
+        }
+    }
+
+    let mut patch = b"PATCH".to_vec();
+    for (start, end) in spans {
+        if end - 1 > MAX_OFFSET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:06x} is past the offsets IPS can patch", end - 1),
+            ));
+        }
+        let mut offset = start;
+        while offset < end {
+            let run_length = rom[offset..end.min(offset + MAX_LENGTH)]
+                .iter()
+                .take_while(|&&byte| byte == rom[offset])
+                .count();
+            if run_length >= MIN_RUN_LENGTH && offset != EOF_OFFSET {
+                write_offset(&mut patch, offset);
+                patch.extend_from_slice(&[0, 0]);
+                patch.extend_from_slice(&(run_length as u16).to_be_bytes());
+                patch.push(rom[offset]);
+                offset += run_length;
+                continue;
+            }
+            // Rewriting the byte before with its value is the same as leaving it alone
+            let record_start = if offset == EOF_OFFSET {
+                offset - 1
+            } else {
+                offset
+            };
+            let mut record_end = offset + 1;
+            while record_end < end
+                && record_end - record_start < MAX_LENGTH
+                && !starts_run(rom, record_end, end)
+            {
+                record_end += 1;
+            }
+            write_offset(&mut patch, record_start);
+            patch.extend_from_slice(&((record_end - record_start) as u16).to_be_bytes());
+            patch.extend_from_slice(&rom[record_start..record_end]);
+            offset = record_end;
+        }
+    }
+    patch.extend_from_slice(b"EOF");
+    Ok(patch)
+}
+
+/// Whether a run record would start at `offset`
+fn starts_run(rom: &[u8], offset: usize, end: usize) -> bool {
+    offset != EOF_OFFSET
+        && end - offset >= MIN_RUN_LENGTH
+        && rom[offset + 1..offset + MIN_RUN_LENGTH]
+            .iter()
+            .all(|&byte| byte == rom[offset])
+}
+
+fn write_offset(patch: &mut Vec<u8>, offset: usize) {
+    patch.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_and_runs() {
+        let mut rom = vec![0; 0x40];
+        rom[0x10..0x12].copy_from_slice(&[0x3e, 0x07]);
+        rom[0x20..0x30].fill(0xff);
+        rom[0x30] = 0xc9;
+        let patched = (0x10..0x12).chain(0x20..0x31);
+
+        let mut expected = b"PATCH".to_vec();
+        expected.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x02, 0x3e, 0x07]);
+        expected.extend_from_slice(&[0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x10, 0xff]);
+        expected.extend_from_slice(&[0x00, 0x00, 0x30, 0x00, 0x01, 0xc9]);
+        expected.extend_from_slice(b"EOF");
+        assert_eq!(encode(&rom, patched).unwrap(), expected);
+    }
+
+    #[test]
+    fn record_at_eof_offset_moves_back() {
+        let mut rom = vec![0; EOF_OFFSET + 0x10];
+        rom[EOF_OFFSET - 1] = 0x12;
+        rom[EOF_OFFSET..EOF_OFFSET + 0x10].fill(0xaa);
+
+        let mut expected = b"PATCH".to_vec();
+        expected.extend_from_slice(&[0x45, 0x4f, 0x45, 0x00, 0x02, 0x12, 0xaa]);
+        expected.extend_from_slice(&[0x45, 0x4f, 0x47, 0x00, 0x00, 0x00, 0x0f, 0xaa]);
+        expected.extend_from_slice(b"EOF");
+        assert_eq!(
+            encode(&rom, EOF_OFFSET..EOF_OFFSET + 0x10).unwrap(),
+            expected
+        );
+    }
+}
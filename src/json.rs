@@ -0,0 +1,144 @@
+//! Export of the whole analysis as JSON, for other tools to build on.
+//!
+//! ```text
+//! {
+//!   "rom_size": 32768,
+//!   "types": [{ "start": 256, "length": 4, "type": "code" }, ...],
+//!   "labels": [{ "address": 336, "name": "Main" }, ...],
+//!   "comments": [{ "address": 336, "comment": "Waits for VBlank" }, ...],
+//!   "banks": [{ "address": 339, "bank": 3 }, ...],
+//!   "bank_ranges": [{ "start": 16384, "length": 416, "bank": 3 }, ...],
+//!   "xrefs": [{ "address": 336, "from": [344] }, ...]
+//! }
+//! ```
+//!
+//! Addresses are offsets in the ROM, in decimal as JSON has no hexadecimal numbers. Byte types
+//! are runs of consecutive bytes sharing the same type and cover the whole ROM. Like in the
+//! listing, only the first byte of an instruction has the code type. Every list is sorted by
+//! address.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::disassembler::{ByteType, Disassembler};
+use crate::gb::GameBoy;
+
+#[derive(Serialize)]
+struct Analysis<'a> {
+    rom_size: usize,
+    types: Vec<TypeRun>,
+    labels: Vec<Label<'a>>,
+    comments: Vec<Comment<'a>>,
+    banks: Vec<Bank>,
+    bank_ranges: Vec<BankRange>,
+    xrefs: Vec<Xrefs<'a>>,
+}
+
+#[derive(Serialize)]
+struct TypeRun {
+    start: usize,
+    length: usize,
+    #[serde(rename = "type")]
+    byte_type: ByteType,
+}
+
+#[derive(Serialize)]
+struct Label<'a> {
+    address: usize,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct Comment<'a> {
+    address: usize,
+    comment: &'a str,
+}
+
+#[derive(Serialize)]
+struct Bank {
+    address: usize,
+    bank: usize,
+}
+
+#[derive(Serialize)]
+struct BankRange {
+    start: usize,
+    length: usize,
+    bank: usize,
+}
+
+#[derive(Serialize)]
+struct Xrefs<'a> {
+    address: usize,
+    from: &'a [usize],
+}
+
+pub fn export(path: &Path, disassembler: &Disassembler<GameBoy>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    let rom_size = disassembler.rom().len();
+    let mut types = Vec::new();
+    let mut start = 0;
+    while start < rom_size {
+        let byte_type = disassembler.byte_type(start);
+        let mut end = start + 1;
+        while end < rom_size && disassembler.byte_type(end) == byte_type {
+            end += 1;
+        }
+        types.push(TypeRun {
+            start,
+            length: end - start,
+            byte_type,
+        });
+        start = end;
+    }
+
+    let mut labels: Vec<Label> = disassembler
+        .labels()
+        .map(|(address, name)| Label { address, name })
+        .collect();
+    labels.sort_unstable_by_key(|label| label.address);
+
+    let mut comments: Vec<Comment> = disassembler
+        .comments()
+        .map(|(address, comment)| Comment { address, comment })
+        .collect();
+    comments.sort_unstable_by_key(|comment| comment.address);
+
+    let mut banks: Vec<Bank> = disassembler
+        .banks()
+        .map(|(address, bank)| Bank { address, bank })
+        .collect();
+    banks.sort_unstable_by_key(|bank| bank.address);
+
+    let bank_ranges = disassembler
+        .bank_ranges()
+        .map(|(start, end, bank)| BankRange {
+            start,
+            length: end - start,
+            bank,
+        })
+        .collect();
+
+    let mut xrefs: Vec<Xrefs> = disassembler
+        .all_xrefs()
+        .map(|(address, from)| Xrefs { address, from })
+        .collect();
+    xrefs.sort_unstable_by_key(|xrefs| xrefs.address);
+
+    let analysis = Analysis {
+        rom_size,
+        types,
+        labels,
+        comments,
+        banks,
+        bank_ranges,
+        xrefs,
+    };
+    serde_json::to_writer_pretty(&mut file, &analysis)?;
+    writeln!(file)?;
+    file.flush()
+}
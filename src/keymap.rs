@@ -0,0 +1,319 @@
+//! Key bindings of the commands.
+//!
+//! Bindings can be changed in a simple line-based text file, one binding per line:
+//!
+//! ```text
+//! # Arrow keys instead of j/k
+//! move-down down
+//! move-up up
+//! quit Q
+//! ```
+//!
+//! Keys are either a single character or the name of a special key. Binding an action replaces
+//! all of its default keys, an action can be bound to several keys by listing it several times.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use pancurses::Input;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    StartSelection,
+    MoveDown,
+    MoveUp,
+    MarkCode,
+    MarkData,
+    MarkPointers,
+    MarkText,
+    MarkDataLength,
+    GoToAddress,
+    SearchBytes,
+    FindInstruction,
+    GoToLabel,
+    NextUnknown,
+    NextLabel,
+    PreviousLabel,
+    Follow,
+    GoToOperand,
+    FollowBack,
+    FollowForward,
+    ShowFollowStack,
+    Xrefs,
+    SetBookmark,
+    GoToBookmark,
+    Undo,
+    Redo,
+    ShowHeader,
+    SetBank,
+    SetCurrentBank,
+    Save,
+    ExportAsm,
+    ExportSymbols,
+    ExportJson,
+    ExportCallGraph,
+    SetComment,
+    SetLabel,
+    RenameLabel,
+    ToggleSplit,
+    SwitchPane,
+    ToggleHexDump,
+    ToggleRegisterNames,
+    TogglePadding,
+    Analyze,
+    CycleImmediates,
+    ClearType,
+    ToggleTimingMarks,
+    FindFlagSetter,
+    PatchBytes,
+    RevertPatches,
+    ShowPatches,
+    WritePatchedRom,
+    ExportIps,
+    Help,
+}
+
+const ACTIONS: [(Action, &str); 53] = [
+    (Action::Quit, "quit"),
+    (Action::StartSelection, "start-selection"),
+    (Action::MoveDown, "move-down"),
+    (Action::MoveUp, "move-up"),
+    (Action::MarkCode, "mark-code"),
+    (Action::MarkData, "mark-data"),
+    (Action::MarkPointers, "mark-pointers"),
+    (Action::MarkText, "mark-text"),
+    (Action::MarkDataLength, "mark-data-length"),
+    (Action::GoToAddress, "go-to-address"),
+    (Action::SearchBytes, "search-bytes"),
+    (Action::FindInstruction, "find-instruction"),
+    (Action::GoToLabel, "go-to-label"),
+    (Action::NextUnknown, "next-unknown"),
+    (Action::NextLabel, "next-label"),
+    (Action::PreviousLabel, "previous-label"),
+    (Action::Follow, "follow"),
+    (Action::GoToOperand, "go-to-operand"),
+    (Action::FollowBack, "follow-back"),
+    (Action::FollowForward, "follow-forward"),
+    (Action::ShowFollowStack, "show-follow-stack"),
+    (Action::Xrefs, "xrefs"),
+    (Action::SetBookmark, "set-bookmark"),
+    (Action::GoToBookmark, "go-to-bookmark"),
+    (Action::Undo, "undo"),
+    (Action::Redo, "redo"),
+    (Action::ShowHeader, "show-header"),
+    (Action::SetBank, "set-bank"),
+    (Action::SetCurrentBank, "set-current-bank"),
+    (Action::Save, "save"),
+    (Action::ExportAsm, "export-asm"),
+    (Action::ExportSymbols, "export-symbols"),
+    (Action::ExportJson, "export-json"),
+    (Action::ExportCallGraph, "export-call-graph"),
+    (Action::SetComment, "set-comment"),
+    (Action::SetLabel, "set-label"),
+    (Action::RenameLabel, "rename-label"),
+    (Action::ToggleSplit, "toggle-split"),
+    (Action::SwitchPane, "switch-pane"),
+    (Action::ToggleHexDump, "toggle-hex-dump"),
+    (Action::ToggleRegisterNames, "toggle-register-names"),
+    (Action::TogglePadding, "toggle-padding"),
+    (Action::Analyze, "analyze"),
+    (Action::CycleImmediates, "cycle-immediates"),
+    (Action::ClearType, "clear-type"),
+    (Action::ToggleTimingMarks, "toggle-timing-marks"),
+    (Action::FindFlagSetter, "find-flag-setter"),
+    (Action::PatchBytes, "patch-bytes"),
+    (Action::RevertPatches, "revert-patches"),
+    (Action::ShowPatches, "show-patches"),
+    (Action::WritePatchedRom, "write-patched-rom"),
+    (Action::ExportIps, "export-ips"),
+    (Action::Help, "help"),
+];
+
+const DEFAULT_BINDINGS: [(Action, Input); 54] = [
+    (Action::Quit, Input::Character('q')),
+    (Action::Quit, Input::Character('\u{1b}')),
+    (Action::StartSelection, Input::Character('v')),
+    (Action::MoveDown, Input::Character('j')),
+    (Action::MoveUp, Input::Character('k')),
+    (Action::MarkCode, Input::Character('c')),
+    (Action::MarkData, Input::Character('d')),
+    (Action::MarkPointers, Input::Character('w')),
+    (Action::MarkText, Input::Character('t')),
+    (Action::MarkDataLength, Input::Character('D')),
+    (Action::GoToAddress, Input::Character('G')),
+    (Action::SearchBytes, Input::Character('/')),
+    (Action::FindInstruction, Input::Character('n')),
+    (Action::GoToLabel, Input::Character('g')),
+    (Action::NextUnknown, Input::Character('N')),
+    (Action::NextLabel, Input::Character(']')),
+    (Action::PreviousLabel, Input::Character('[')),
+    (Action::Follow, Input::Character('f')),
+    (Action::GoToOperand, Input::Character('a')),
+    (Action::FollowBack, Input::Character('o')),
+    (Action::FollowForward, Input::Character('i')),
+    (Action::ShowFollowStack, Input::Character('O')),
+    (Action::Xrefs, Input::Character('x')),
+    (Action::SetBookmark, Input::Character('m')),
+    (Action::GoToBookmark, Input::Character('\'')),
+    (Action::Undo, Input::Character('u')),
+    (Action::Redo, Input::Character('U')),
+    (Action::ShowHeader, Input::Character('H')),
+    (Action::SetBank, Input::Character('b')),
+    (Action::SetCurrentBank, Input::Character('B')),
+    (Action::Save, Input::Character('s')),
+    (Action::ExportAsm, Input::Character('e')),
+    (Action::ExportSymbols, Input::Character('E')),
+    (Action::ExportJson, Input::Character('J')),
+    (Action::ExportCallGraph, Input::Character('X')),
+    (Action::SetComment, Input::Character(';')),
+    (Action::SetLabel, Input::Character('l')),
+    (Action::RenameLabel, Input::Character('L')),
+    (Action::ToggleSplit, Input::Character('S')),
+    (Action::SwitchPane, Input::Character('\t')),
+    (Action::ToggleHexDump, Input::Character('h')),
+    (Action::ToggleRegisterNames, Input::Character('R')),
+    (Action::TogglePadding, Input::Character('z')),
+    (Action::Analyze, Input::Character('A')),
+    (Action::CycleImmediates, Input::Character('#')),
+    (Action::ClearType, Input::Character('C')),
+    (Action::ToggleTimingMarks, Input::Character('T')),
+    (Action::FindFlagSetter, Input::Character('F')),
+    (Action::PatchBytes, Input::Character('p')),
+    (Action::RevertPatches, Input::Character('P')),
+    (Action::ShowPatches, Input::Character('V')),
+    (Action::WritePatchedRom, Input::Character('W')),
+    (Action::ExportIps, Input::Character('I')),
+    (Action::Help, Input::Character('?')),
+];
+
+pub struct KeyMap {
+    bindings: HashMap<Input, Action>,
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|&(action, input)| (input, action))
+                .collect(),
+        }
+    }
+}
+
+impl KeyMap {
+    /// Loads the bindings of `path` on top of the default ones
+    pub fn load(path: &Path) -> io::Result<KeyMap> {
+        let file = BufReader::new(File::open(path)?);
+        let mut bindings = Vec::new();
+        for (line_index, line) in file.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let binding = parse_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid line {}: {}", line_index + 1, line),
+                )
+            })?;
+            bindings.push(binding);
+        }
+
+        let mut key_map = KeyMap::default();
+        let rebound: HashSet<Action> = bindings.iter().map(|&(action, _)| action).collect();
+        key_map
+            .bindings
+            .retain(|_, action| !rebound.contains(action));
+        for (action, input) in bindings {
+            key_map.bindings.insert(input, action);
+        }
+        Ok(key_map)
+    }
+
+    pub fn action(&self, input: Input) -> Option<Action> {
+        self.bindings.get(&input).copied()
+    }
+
+    /// Returns the name of every action along with the keys bound to it, in the order of the
+    /// actions. Actions without a key are left out.
+    pub fn describe(&self) -> Vec<(&'static str, Vec<String>)> {
+        ACTIONS
+            .iter()
+            .filter_map(|&(action, name)| {
+                let mut keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|&(_, &bound)| bound == action)
+                    .map(|(&input, _)| key_name(input))
+                    .collect();
+                keys.sort_unstable();
+                if keys.is_empty() {
+                    None
+                } else {
+                    Some((name, keys))
+                }
+            })
+            .collect()
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Action, Input)> {
+    let (action, key) = line.split_once(' ')?;
+    let action = ACTIONS
+        .iter()
+        .find(|&&(_, name)| name == action)
+        .map(|&(action, _)| action)?;
+    Some((action, parse_key(key.trim())?))
+}
+
+/// Names a key the way the bindings file does
+fn key_name(input: Input) -> String {
+    let name = match input {
+        Input::KeyUp => "up",
+        Input::KeyDown => "down",
+        Input::KeyLeft => "left",
+        Input::KeyRight => "right",
+        Input::KeyPPage => "pageup",
+        Input::KeyNPage => "pagedown",
+        Input::KeyHome => "home",
+        Input::KeyEnd => "end",
+        Input::Character('\n') => "enter",
+        Input::Character(' ') => "space",
+        Input::Character('\t') => "tab",
+        Input::Character('\u{1b}') => "esc",
+        Input::Character(key) => return key.to_string(),
+        input => return format!("{:?}", input),
+    };
+    name.to_string()
+}
+
+fn parse_key(key: &str) -> Option<Input> {
+    let input = match key {
+        "up" => Input::KeyUp,
+        "down" => Input::KeyDown,
+        "left" => Input::KeyLeft,
+        "right" => Input::KeyRight,
+        "pageup" => Input::KeyPPage,
+        "pagedown" => Input::KeyNPage,
+        "home" => Input::KeyHome,
+        "end" => Input::KeyEnd,
+        "enter" => Input::Character('\n'),
+        "space" => Input::Character(' '),
+        "tab" => Input::Character('\t'),
+        "esc" => Input::Character('\u{1b}'),
+        _ => {
+            let mut chars = key.chars();
+            let key = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            Input::Character(key)
+        }
+    };
+    Some(input)
+}
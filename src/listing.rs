@@ -0,0 +1,293 @@
+//! Text of the disassembly listing, shared by the interactive view and `--dump`.
+//!
+//! Lines start with the address and the first bytes of the item, followed by the item itself
+//! from `MNEMONIC_COLUMN` on. Labels take a line of their own.
+
+use std::io::{self, Write};
+
+use crate::disassembler::{AddressRepr, Disassembler};
+use crate::gb::{
+    self, hardware_register_name, Argument, GBInstruction, GameBoy, ResolvedAddress,
+    UnmappedAddress,
+};
+
+/// The column the mnemonics start at, the bytes of a line are shown before it
+pub const MNEMONIC_COLUMN: usize = 20;
+/// The width mnemonics are padded to in the listing so that the operands line up
+const MNEMONIC_WIDTH: usize = 6;
+/// How many bytes fit before the mnemonic column along with the `..` marking truncated lines
+const SHOWN_BYTES: usize = 3;
+
+/// How immediate values are written
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Immediates {
+    Hex,
+    /// Unsigned decimal with the `0n` prefix of the goto prompt, e.g. `0n200`
+    Decimal,
+    /// Like `Decimal`, reading the values as two's complement, e.g. `0n-56`
+    SignedDecimal,
+}
+
+impl Immediates {
+    /// The format following this one, to cycle through them
+    pub fn next(self) -> Immediates {
+        match self {
+            Immediates::Hex => Immediates::Decimal,
+            Immediates::Decimal => Immediates::SignedDecimal,
+            Immediates::SignedDecimal => Immediates::Hex,
+        }
+    }
+}
+
+/// Formats the items of a disassembly
+pub struct Formatter<'a> {
+    pub disassembler: &'a Disassembler<GameBoy>,
+    /// Whether accesses to the I/O registers are shown with their `hardware.inc` names
+    pub register_names: bool,
+    pub immediates: Immediates,
+}
+
+impl Formatter<'_> {
+    /// Formats what a line shows after its bytes, None for labels
+    pub fn format_line(&self, repr: &AddressRepr<GBInstruction>) -> Option<String> {
+        let text = match *repr {
+            AddressRepr::Label { .. } => return None,
+            AddressRepr::Unknown { .. } => "??".to_string(),
+            AddressRepr::Data { bytes, .. } => {
+                let values: Vec<String> =
+                    bytes.iter().map(|byte| format!("${:02x}", byte)).collect();
+                format!("db {}", values.join(", "))
+            }
+            AddressRepr::Dump { bytes, .. } => format_dump(bytes),
+            AddressRepr::Padding { byte, length, .. } => {
+                format!("... {:#x} bytes of {:#04x} ...", length, byte)
+            }
+            AddressRepr::Text { bytes, .. } => format!("db {}", format_text(bytes)),
+            AddressRepr::Pointer { address, bytes } => self.format_pointer(address, bytes),
+            AddressRepr::Code {
+                address,
+                ref instruction,
+                ..
+            } => self.format_instruction(address, instruction),
+            AddressRepr::Undecoded { .. } => "Undecoded instruction".to_string(),
+        };
+        Some(text)
+    }
+
+    /// Formats an instruction for the listing, with the arguments aligned on a column
+    pub fn format_instruction(&self, read_at: usize, instruction: &GBInstruction) -> String {
+        self.mnemonic_with_operands(read_at, instruction, MNEMONIC_WIDTH)
+    }
+
+    /// Formats an instruction with its operands, e.g. `LD A, 05`. The mnemonic is padded to
+    /// `mnemonic_width` for the operands to line up, and always separated from them by a space.
+    pub fn mnemonic_with_operands(
+        &self,
+        read_at: usize,
+        instruction: &GBInstruction,
+        mnemonic_width: usize,
+    ) -> String {
+        if let GBInstruction::Illegal(opcode) = instruction {
+            return format!("ILLEGAL (db ${:02x})", opcode);
+        }
+        let arguments = self.format_arguments(read_at, instruction);
+        if arguments.is_empty() {
+            instruction.name().to_string()
+        } else {
+            let width = mnemonic_width.saturating_sub(1);
+            format!("{:<2$} {}", instruction.name(), arguments, width)
+        }
+    }
+
+    fn format_arguments(&self, read_at: usize, instruction: &GBInstruction) -> String {
+        instruction
+            .first_argument()
+            .into_iter()
+            .chain(instruction.second_argument())
+            .map(|argument| self.format_argument(read_at, &argument))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn format_argument(&self, read_at: usize, argument: &Argument) -> String {
+        match *argument {
+            Argument::Imm8(value) => match self.immediates {
+                Immediates::Hex => format!("{:02x}", value),
+                Immediates::Decimal => format!("0n{}", value),
+                Immediates::SignedDecimal => format!("0n{}", value as i8),
+            },
+            Argument::Imm16(value) => match self.immediates {
+                Immediates::Hex => format!("{:04x}", value),
+                Immediates::Decimal => format!("0n{}", value),
+                Immediates::SignedDecimal => format!("0n{}", value as i16),
+            },
+            Argument::Rel8(value) => {
+                // Use the same target as the code follow. The displacement is shown relative
+                // to the start of the instruction.
+                let displacement = value as isize + 2;
+                match self.disassembler.branch_target(read_at) {
+                    Some(target) => match self.disassembler.label(target) {
+                        Some(label) => format!("{} (.{:+})", label, displacement),
+                        None => format!("({:06x}) (.{:+})", target, displacement),
+                    },
+                    None => format!("(past end) (.{:+})", displacement),
+                }
+            }
+            Argument::Reg8(register) => format!("{}", register),
+            Argument::Reg16(register) => format!("{}", register),
+            Argument::Address(unmapped_address) => {
+                match gb::resolve_physical_address(self.disassembler, read_at, unmapped_address) {
+                    ResolvedAddress::Physical(address) => match self.disassembler.label(address) {
+                        Some(label) => label.to_string(),
+                        None => format!("({:06x})", address),
+                    },
+                    ResolvedAddress::UnknownBank(offset) => format!("(??:{:04x})", offset),
+                    ResolvedAddress::PastEnd(address) => format!("(past end:{:06x})", address),
+                    ResolvedAddress::System(region, address) => {
+                        match hardware_register_name(address).filter(|_| self.register_names) {
+                            Some(name) => format!("({})", name),
+                            None => format!("({}:{:04x})", region, address),
+                        }
+                    }
+                }
+            }
+            Argument::IndirectReg16(register) => format!("({})", register),
+            Argument::IndirectHLinc => "(HL+)".to_string(),
+            Argument::IndirectHLdec => "(HL-)".to_string(),
+            Argument::IndirectC => "(IO:ff00 + C)".to_string(),
+            Argument::ResetVector(reset_vector) => format!("{}", reset_vector),
+            Argument::SignedImm8(value) => self.format_signed(value),
+            Argument::SPRel8(value) => format!("SP{}", self.format_signed(value)),
+            Argument::BitIndex(bit) => format!("{}", bit),
+        }
+    }
+
+    /// Formats an offset, always signed, with an explicit sign in hex
+    fn format_signed(&self, value: i8) -> String {
+        match self.immediates {
+            Immediates::Hex => signed_hex(value),
+            Immediates::Decimal | Immediates::SignedDecimal => format!("0n{}", value),
+        }
+    }
+
+    /// Formats a pointer table entry, with the address it points to
+    fn format_pointer(&self, address: usize, bytes: &[u8]) -> String {
+        match *bytes {
+            [low, high] => {
+                let pointer = Argument::Address(UnmappedAddress(u16::from_le_bytes([low, high])));
+                format!("dw {}", self.format_argument(address, &pointer))
+            }
+            // The table ends in the middle of the pointer
+            _ => {
+                let values: Vec<String> =
+                    bytes.iter().map(|byte| format!("${:02x}", byte)).collect();
+                format!("db {}", values.join(", "))
+            }
+        }
+    }
+}
+
+/// Returns the address of a line and the bytes shown before its mnemonic column, None for
+/// labels. Hex dump rows and padding show their bytes themselves.
+pub fn line_start<'a>(repr: &'a AddressRepr<GBInstruction>) -> Option<(usize, &'a [u8])> {
+    match repr {
+        AddressRepr::Label { .. } => None,
+        AddressRepr::Unknown { address, byte } | AddressRepr::Undecoded { address, byte } => {
+            Some((*address, std::slice::from_ref(byte)))
+        }
+        AddressRepr::Dump { address, .. } | AddressRepr::Padding { address, .. } => {
+            Some((*address, &[]))
+        }
+        AddressRepr::Data { address, bytes }
+        | AddressRepr::Text { address, bytes }
+        | AddressRepr::Pointer { address, bytes }
+        | AddressRepr::Code { address, bytes, .. } => Some((*address, bytes)),
+    }
+}
+
+/// Formats the bytes of a line, only the first ones if they don't fit before the mnemonic
+/// column
+pub fn format_bytes(bytes: &[u8]) -> String {
+    let shown = &bytes[..bytes.len().min(SHOWN_BYTES)];
+    let mut text: String = shown.iter().map(|byte| format!("{:02x} ", byte)).collect();
+    if shown.len() < bytes.len() {
+        text += "..";
+    }
+    text
+}
+
+/// Writes the whole listing to `out`, with the comments
+pub fn print(out: &mut impl Write, formatter: &Formatter) -> io::Result<()> {
+    for repr in formatter.disassembler.iter_from(0) {
+        let (address, bytes) = match line_start(&repr) {
+            Some(start) => start,
+            None => {
+                if let AddressRepr::Label { name, .. } = repr {
+                    writeln!(out, "{}:", name)?;
+                }
+                continue;
+            }
+        };
+        let mut line = format!("{:06x}: ", address);
+        if !bytes.is_empty() {
+            let width = MNEMONIC_COLUMN - line.len();
+            line += &format!("{:<1$}", format_bytes(bytes), width);
+        }
+        line += &formatter.format_line(&repr).unwrap_or_default();
+        if let Some(comment) = formatter.disassembler.comment(address) {
+            line += &format!(" ; {}", comment);
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Formats a hex dump row as its bytes followed by their printable characters, like xxd
+fn format_dump(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let ascii: String = bytes
+        .iter()
+        .map(|&byte| {
+            if (0x20..=0x7e).contains(&byte) {
+                byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!("{:<48}{}", hex.join(" "), ascii)
+}
+
+/// Formats a string as a quoted literal, with non-printable bytes such as the terminator
+/// written separately as hex values, e.g. `"HELLO", $00`
+fn format_text(bytes: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    for &byte in bytes {
+        if (0x20..=0x7e).contains(&byte) {
+            if byte == b'"' || byte == b'\\' {
+                literal.push('\\');
+            }
+            literal.push(byte as char);
+        } else {
+            if !literal.is_empty() {
+                parts.push(format!("\"{}\"", literal));
+                literal.clear();
+            }
+            parts.push(format!("${:02x}", byte));
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(format!("\"{}\"", literal));
+    }
+    parts.join(", ")
+}
+
+/// Formats a signed byte as hex with an explicit sign, e.g. `+05` or `-10`
+fn signed_hex(value: i8) -> String {
+    if value < 0 {
+        format!("-{:02x}", value.unsigned_abs())
+    } else {
+        format!("+{:02x}", value)
+    }
+}
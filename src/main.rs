@@ -1,238 +1,1403 @@
-use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
-use pancurses::{Input, Window};
+use pancurses::{chtype, Input, Window};
 
+mod asm;
 mod disassembler;
+mod dot;
 mod gb;
+mod header;
+mod ips;
+mod json;
+mod keymap;
+mod listing;
+mod project;
+mod sym;
 use disassembler::*;
 use gb::*;
+use header::RomHeader;
+use keymap::{Action, KeyMap};
+use listing::{Formatter, Immediates, MNEMONIC_COLUMN};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum ByteType {
-    Unknown,
-    Data,
-    Code,
-}
+/// Color pairs used to tell the kinds of lines apart
+const CODE_COLOR: i16 = 1;
+const DATA_COLOR: i16 = 2;
+const LABEL_COLOR: i16 = 3;
+const TARGET_COLOR: i16 = 4;
+const PATCH_COLOR: i16 = 5;
+
+/// The row of the messages telling how the last command went
+const STATUS_ROW: i32 = 2;
 
-struct ByteStore {
-    pub bytes: Vec<u8>,
-    pub types: Vec<ByteType>,
+/// The position in a view of the ROM
+#[derive(Clone, Copy)]
+struct Pane {
+    base_address: usize,
+    selected_address: usize,
+    selection_start: Option<usize>,
 }
 
-enum ResolvedAddress {
-    Physical(usize),
-    UnknownBank(u16),
-    System(u16),
+/// Where the exports of a ROM are written, named after it
+struct ExportPaths {
+    asm: PathBuf,
+    sym: PathBuf,
+    json: PathBuf,
+    /// The call graph
+    dot: PathBuf,
+    /// The ROM with the patches applied
+    patched: PathBuf,
+    /// The patches as an IPS patch
+    ips: PathBuf,
 }
 
-impl ResolvedAddress {
-    pub fn get(&self) -> Option<usize> {
-        match self {
-            &Self::Physical(address) => Some(address),
-            _ => None,
+impl ExportPaths {
+    fn new(rom_path: &Path) -> ExportPaths {
+        // game.gb is written to game.patched.gb
+        let patched = match rom_path.extension() {
+            Some(extension) => {
+                rom_path.with_extension(format!("patched.{}", extension.to_string_lossy()))
+            }
+            None => rom_path.with_extension("patched"),
+        };
+        ExportPaths {
+            asm: rom_path.with_extension("asm"),
+            sym: rom_path.with_extension("sym"),
+            json: rom_path.with_extension("json"),
+            dot: rom_path.with_extension("dot"),
+            patched,
+            ips: rom_path.with_extension("ips"),
         }
     }
 }
 
-struct Application {
+struct Application<Arch: Architecture> {
     running: bool,
     base_address: usize,
     selected_address: usize,
+    /// Where the selection started when a span is being selected with `v`
+    selection_start: Option<usize>,
+    /// The count typed before a motion, like `10j` in vi
+    count: Option<usize>,
 
     window: Window,
-    byte_store: ByteStore,
+    keys: KeyMap,
+    /// The address shown on each row of the last drawn frame and whether the row is in the
+    /// active pane, used to select lines by clicking
+    line_rows: Vec<(i32, bool, usize)>,
+    /// The inactive pane when the view is split, the active one uses the fields above
+    split_pane: Option<Pane>,
+    /// Whether the inactive pane is drawn above the active one
+    split_pane_on_top: bool,
+    /// Whether data is shown as assembly or as a hex dump
+    layout: Layout,
+    /// Whether accesses to the I/O registers are shown with their `hardware.inc` names
+    register_names: bool,
+    immediates: Immediates,
+    /// Whether the instructions accessing the LCD timing registers are flagged in the margin
+    timing_marks: bool,
+    /// Message shown under the header, used to report the result of commands
+    status: String,
+    /// The byte pattern being typed at the search prompt, lines holding a match are
+    /// highlighted
+    search_highlight: Vec<Option<u8>>,
+    disassembler: Disassembler<Arch>,
+    /// The cartridge header, None if the ROM is too small to have one
+    header: Option<RomHeader>,
     type_changes: Vec<(ByteType, usize)>,
-    labels: HashMap<usize, String>,
-    banks: HashMap<usize, usize>,
+    /// Where the annotations are saved
+    project_path: PathBuf,
+    exports: ExportPaths,
+    /// Where the position and the follow history are kept between runs
+    session_path: PathBuf,
 
     /// Contains the addresses from which a follow command was issued, used to rewind follows
     follow_stack: Vec<usize>,
+    /// The entry being visited, the length of the stack when not visiting one
     follow_stack_top: usize,
 }
 
-impl Application {
-    pub fn new(rom_data: Vec<u8>) -> Application {
-        let rom_data_length = rom_data.len();
+/// The parts of the viewer that work the same for every architecture
+impl<Arch: Architecture> Application<Arch> {
+    pub fn new(
+        rom_data: Vec<u8>,
+        project_path: PathBuf,
+        exports: ExportPaths,
+    ) -> Application<Arch> {
+        // Escape cancels prompts, don't wait a whole second for an escape sequence after it
+        if std::env::var_os("ESCDELAY").is_none() {
+            std::env::set_var("ESCDELAY", "25");
+        }
         let window = pancurses::initscr();
         pancurses::noecho();
         pancurses::curs_set(0);
+        window.keypad(true);
+        if pancurses::has_colors() {
+            pancurses::start_color();
+            pancurses::use_default_colors();
+            pancurses::init_pair(CODE_COLOR, pancurses::COLOR_GREEN, -1);
+            pancurses::init_pair(DATA_COLOR, pancurses::COLOR_YELLOW, -1);
+            pancurses::init_pair(LABEL_COLOR, pancurses::COLOR_CYAN, -1);
+            pancurses::init_pair(TARGET_COLOR, pancurses::COLOR_MAGENTA, -1);
+            pancurses::init_pair(PATCH_COLOR, pancurses::COLOR_RED, -1);
+        }
+        pancurses::mousemask(
+            pancurses::BUTTON1_CLICKED | pancurses::BUTTON1_PRESSED,
+            std::ptr::null_mut(),
+        );
         Application {
             running: false,
             base_address: 0,
             selected_address: 0,
+            selection_start: None,
+            count: None,
 
             window,
-            byte_store: ByteStore {
-                bytes: rom_data,
-                types: vec![ByteType::Unknown; rom_data_length],
-            },
+            keys: KeyMap::default(),
+            line_rows: Vec::new(),
+            split_pane: None,
+            split_pane_on_top: false,
+            layout: Layout::Listing,
+            register_names: true,
+            immediates: Immediates::Hex,
+            timing_marks: false,
+            status: String::new(),
+            search_highlight: Vec::new(),
+            header: RomHeader::parse(&rom_data),
+            disassembler: Disassembler::new(rom_data),
             type_changes: Vec::new(),
-            labels: HashMap::new(),
-            banks: HashMap::new(),
+            session_path: project::session_path(&project_path),
+            project_path,
+            exports,
 
             follow_stack: Vec::new(),
             follow_stack_top: 0,
         }
     }
 
+    /// Loads the annotations from the project file, if there is one
+    pub fn load_project(&mut self) {
+        if !self.project_path.exists() {
+            return;
+        }
+        self.status = match project::load(&self.project_path, &mut self.disassembler) {
+            Ok(session) => {
+                self.follow_stack = session.follow_stack;
+                self.follow_stack_top = self.follow_stack.len();
+                format!("Loaded {}", self.project_path.display())
+            }
+            Err(error) => format!("Unable to load {}: {}", self.project_path.display(), error),
+        };
+        self.disassembler.update_xrefs();
+    }
+
+    /// Replaces the default key bindings with the ones from `path`
+    pub fn load_keys(&mut self, path: &Path) {
+        match KeyMap::load(path) {
+            Ok(keys) => self.keys = keys,
+            Err(error) => self.status = format!("Unable to load {}: {}", path.display(), error),
+        }
+    }
+
+    /// Restores where the user was when quitting the last time, if it was the same ROM
+    pub fn load_session(&mut self) {
+        if !self.session_path.exists() {
+            return;
+        }
+        match project::load_session(&self.session_path, &self.disassembler.original_rom()) {
+            Ok(Some(session)) => {
+                if let Some((base_address, selected_address)) = session.position {
+                    self.base_address = base_address;
+                    self.selected_address = selected_address;
+                }
+                self.follow_stack = session.follow_stack;
+                self.follow_stack_top = self.follow_stack.len();
+            }
+            Ok(None) => {}
+            Err(error) => {
+                self.status = format!("Unable to load {}: {}", self.session_path.display(), error)
+            }
+        }
+    }
+
+    fn save_session(&self) -> io::Result<()> {
+        project::save_session(
+            &self.session_path,
+            &self.disassembler.original_rom(),
+            &self.session(),
+        )
+    }
+
+    fn session(&self) -> project::Session {
+        project::Session {
+            follow_stack: self.follow_stack.clone(),
+            position: Some((self.base_address, self.selected_address)),
+        }
+    }
+
+    fn save_project(&mut self) {
+        let session = self.session();
+        self.status = match project::save(&self.project_path, &self.disassembler, &session) {
+            Ok(()) => format!("Saved {}", self.project_path.display()),
+            Err(error) => format!("Unable to save {}: {}", self.project_path.display(), error),
+        };
+    }
+
+    /// Overwrites the bytes from the selection or the selected address with the ones typed at
+    /// the prompt, `??` keeps a byte as it is
+    fn patch_bytes(&mut self) {
+        let start = self
+            .selection()
+            .map_or(self.selected_address, |(start, _)| start);
+        let input = self.read_line("Patch bytes: ").unwrap_or_default();
+        if input.is_empty() {
+            return;
+        }
+        let bytes = match parse_byte_pattern(&input) {
+            Some(bytes) => bytes,
+            None => {
+                self.status = format!("Invalid bytes: {}", input);
+                return;
+            }
+        };
+        if start + bytes.len() > self.disassembler.rom().len() {
+            self.status = "The bytes go past the end of the ROM".to_string();
+            return;
+        }
+        for (address, byte) in (start..).zip(bytes) {
+            if let Some(byte) = byte {
+                self.disassembler.patch_byte(address, byte);
+            }
+        }
+        self.selection_start = None;
+        self.rom_changed();
+    }
+
+    /// Restores the original bytes of the selection or of the selected line
+    fn revert_patches(&mut self) {
+        let (start, end) = self.selection().unwrap_or_else(|| {
+            let end = self
+                .disassembler
+                .next_line(self.selected_address, self.layout);
+            (self.selected_address, end)
+        });
+        self.selection_start = None;
+        let reverted = (start..end)
+            .filter(|&address| self.disassembler.revert_patch(address))
+            .count();
+        if reverted == 0 {
+            self.status = "No patched bytes here".to_string();
+            return;
+        }
+        self.rom_changed();
+        self.status = format!("Reverted {} bytes", reverted);
+    }
+
+    /// Lists the runs of patched bytes with their original and new values, and goes to the
+    /// chosen one
+    fn choose_patch(&mut self) {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (address, original) in self.disassembler.patches() {
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == address => bytes.push(original),
+                _ => runs.push((address, vec![original])),
+            }
+        }
+        if runs.is_empty() {
+            self.status = "No patched bytes".to_string();
+            return;
+        }
+        let hex = |bytes: &[u8]| {
+            let values: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            values.join(" ")
+        };
+        let items: Vec<String> = runs
+            .iter()
+            .map(|(start, original)| {
+                let patched = &self.disassembler.rom()[*start..*start + original.len()];
+                format!("{:06x}: {} -> {}", start, hex(original), hex(patched))
+            })
+            .collect();
+        if let Some(index) = self.choose("Patched bytes", &items) {
+            let address = self.disassembler.align_to_line(runs[index].0, self.layout);
+            self.push_follow(self.selected_address);
+            self.base_address = address;
+            self.selected_address = address;
+        }
+    }
+
+    /// Writes the ROM with the patches applied, the original file is left untouched
+    fn write_patched_rom(&mut self) {
+        let written = File::create(&self.exports.patched)
+            .and_then(|mut file| file.write_all(self.disassembler.rom()));
+        self.status = match written {
+            Ok(()) => format!(
+                "Wrote {} with {} patched bytes",
+                self.exports.patched.display(),
+                self.disassembler.patches().count()
+            ),
+            Err(error) => format!(
+                "Unable to write {}: {}",
+                self.exports.patched.display(),
+                error
+            ),
+        };
+    }
+
+    fn export_ips(&mut self) {
+        if self.disassembler.patches().next().is_none() {
+            self.status = "No patched bytes to export".to_string();
+            return;
+        }
+        self.status = match ips::export(&self.exports.ips, &self.disassembler) {
+            Ok(()) => format!("Exported {}", self.exports.ips.display()),
+            Err(error) => format!("Unable to export {}: {}", self.exports.ips.display(), error),
+        };
+    }
+
+    /// Updates what is derived from the bytes of the ROM after they were patched
+    fn rom_changed(&mut self) {
+        self.header = RomHeader::parse(self.disassembler.rom());
+        self.disassembler.update_xrefs();
+    }
+
+    /// Parses an address typed at the goto prompt: hex (`4010`), decimal (`0n16400`), relative
+    /// to the selected address (`+10`, `-8`) or a banked address as `bank:offset` (`3:4010`)
+    fn parse_goto_address(&self, input: &str) -> Option<usize> {
+        if let Some(decimal) = input.strip_prefix("0n") {
+            decimal.parse().ok()
+        } else if let Some(offset) = input.strip_prefix('+') {
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            self.selected_address.checked_add(offset)
+        } else if let Some(offset) = input.strip_prefix('-') {
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            self.selected_address.checked_sub(offset)
+        } else if let Some((bank, offset)) = input.split_once(':') {
+            let bank = usize::from_str_radix(bank, 16).ok()?;
+            let offset = u16::from_str_radix(offset, 16).ok()?;
+            banked_address(bank, offset)
+        } else {
+            usize::from_str_radix(input, 16).ok()
+        }
+    }
+
+    /// Jumps to the label called `name`. Without an exact match, jumps to the label starting
+    /// with `name` if there is only one, or lets the user choose between them.
+    fn go_to_label(&mut self, name: &str) {
+        let mut matches: Vec<(usize, &str)> =
+            match self.disassembler.labels().find(|&(_, label)| label == name) {
+                Some(exact_match) => vec![exact_match],
+                None => self
+                    .disassembler
+                    .labels()
+                    .filter(|(_, label)| label.starts_with(name))
+                    .collect(),
+            };
+        matches.sort_unstable_by_key(|&(_, label)| label);
+
+        let address = match matches.len() {
+            0 => {
+                self.status = format!("No label matches {}", name);
+                return;
+            }
+            1 => matches[0].0,
+            _ => {
+                let items: Vec<String> = matches
+                    .iter()
+                    .map(|(address, label)| format!("{:06x}: {}", address, label))
+                    .collect();
+                let title = format!("Labels starting with {}", name);
+                match self.choose(&title, &items) {
+                    Some(index) => matches[index].0,
+                    None => return,
+                }
+            }
+        };
+        self.push_follow(self.selected_address);
+        self.base_address = address;
+        self.selected_address = address;
+    }
+
+    fn label_address(&self, name: &str) -> Option<usize> {
+        self.disassembler
+            .labels()
+            .find(|&(_, label)| label == name)
+            .map(|(address, _)| address)
+    }
+
+    /// Names `address` unless another address already has that name, an empty name removes
+    /// the label. References show the new name right away since they look labels up when drawn.
+    fn set_label(&mut self, address: usize, name: String) {
+        match self.label_address(&name) {
+            Some(other) if other != address => {
+                self.status = format!("{} is already the label of {:06x}", name, other);
+            }
+            _ => {
+                if name.is_empty() {
+                    if let Some(old_name) = self.disassembler.label(address) {
+                        self.status = format!("Removed label {}", old_name);
+                    }
+                }
+                self.disassembler.set_label(address, name);
+            }
+        }
+    }
+
+    /// Shows `items` in place of the listing and lets the user pick one with j/k and enter.
+    /// Returns None if the choice is cancelled with escape or q.
+    fn choose(&self, title: &str, items: &[String]) -> Option<usize> {
+        let mut selected: usize = 0;
+        loop {
+            self.window.clear();
+            self.window.mvaddstr(0, 0, title);
+            self.window.mv(1, 0);
+            self.draw_hline();
+            let height = (self.window.get_max_y() - 2) as usize;
+            let first = (selected + 1).saturating_sub(height);
+            for (index, item) in items.iter().enumerate().skip(first).take(height) {
+                self.window.mvaddstr((2 + index - first) as i32, 0, item);
+                if index == selected {
+                    self.window.mv((2 + index - first) as i32, 0);
+                    self.window
+                        .chgat(self.window.get_max_x(), pancurses::A_REVERSE, 0);
+                }
+            }
+            match self.window.getch() {
+                Some(Input::Character('j')) if selected + 1 < items.len() => selected += 1,
+                Some(Input::Character('k')) if selected > 0 => selected -= 1,
+                Some(Input::Character('\n')) => return Some(selected),
+                Some(Input::Character('\u{1b}')) | Some(Input::Character('q')) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Shows the key bindings and how to read the listing
+    fn show_help(&self) {
+        let entries: Vec<String> = self
+            .keys
+            .describe()
+            .into_iter()
+            .map(|(name, keys)| format!("{:<8} {}", keys.join(" "), name))
+            .collect();
+        // Laid out in columns, read top to bottom
+        let column_width = entries.iter().map(String::len).max().unwrap_or(0) + 2;
+        let columns = (self.window.get_max_x().max(0) as usize / column_width).max(1);
+        let rows = entries.len().div_ceil(columns);
+        let mut lines: Vec<String> = (0..rows)
+            .map(|row| {
+                entries
+                    .iter()
+                    .skip(row)
+                    .step_by(rows)
+                    .map(|entry| format!("{:<1$}", entry, column_width))
+                    .collect()
+            })
+            .collect();
+
+        lines.push(String::new());
+        lines.extend(
+            [
+                "??                   byte of unknown type",
+                "db / dw              data, text and pointers",
+                "... N bytes of $xx   collapsed padding, see toggle-padding",
+                "(WRAM:c000)          address outside of the ROM, by memory region",
+                "(rLY)                hardware register, see toggle-register-names",
+                "(??:4000)            banked address whose bank isn't known, see set-bank",
+                "(.+5)                branch displacement from the instruction",
+                "0n200                decimal immediate, see cycle-immediates",
+            ]
+            .iter()
+            .map(|line| line.to_string()),
+        );
+        self.show_lines("Keys (any key to close)", &lines);
+    }
+
+    /// Shows `lines` in place of the listing until a key is pressed
+    fn show_lines(&self, title: &str, lines: &[String]) {
+        self.window.clear();
+        self.window.mvaddstr(0, 0, title);
+        self.window.mv(1, 0);
+        self.draw_hline();
+        let height = (self.window.get_max_y() - 2) as usize;
+        for (index, line) in lines.iter().take(height).enumerate() {
+            self.window.mvaddstr((2 + index) as i32, 0, line);
+        }
+        self.window.getch();
+    }
+
+    /// Prompts for a line of text on the second row, None if the prompt is cancelled with
+    /// escape. Backspace erases the last character.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        self.read_line_with(prompt, |_, _| {})
+    }
+
+    /// Like `read_line`, calling `on_change` with the input every time it changes, e.g. to
+    /// redraw the listing with it. The prompt is drawn again afterwards.
+    fn read_line_with(
+        &mut self,
+        prompt: &str,
+        mut on_change: impl FnMut(&mut Self, &str),
+    ) -> Option<String> {
+        pancurses::curs_set(2);
+        let mut string = String::new();
+        let string = loop {
+            self.window.mv(1, 0);
+            self.clear_line();
+            self.window.mvaddstr(1, 0, format!("{}{}", prompt, string));
+            match self.window.getch() {
+                Some(Input::Character('\n')) => break Some(string),
+                Some(Input::Character('\u{1b}')) => break None,
+                Some(Input::KeyBackspace)
+                | Some(Input::Character('\u{7f}'))
+                | Some(Input::Character('\u{8}')) => {
+                    string.pop();
+                    on_change(self, &string);
+                }
+                Some(Input::Character(c)) if !c.is_control() => {
+                    string.push(c);
+                    on_change(self, &string);
+                }
+                _ => {}
+            }
+        };
+        pancurses::curs_set(0);
+
+        self.window.mv(1, 0);
+        self.clear_line();
+
+        string
+    }
+
+    /// Asks a yes or no question on the second row, anything but `y` answers no
+    fn confirm(&self, question: &str) -> bool {
+        self.window.mv(1, 0);
+        self.clear_line();
+        self.window.mvaddstr(1, 0, format!("{} (y/n) ", question));
+        let answer = self.window.getch();
+        self.window.mv(1, 0);
+        self.clear_line();
+        answer == Some(Input::Character('y'))
+    }
+
+    /// Prompts for a hexadecimal number, None if the prompt is cancelled or the input isn't one
+    fn read_hex(&mut self, prompt: &str) -> Option<usize> {
+        usize::from_str_radix(self.read_line(prompt)?.trim(), 16).ok()
+    }
+
+    /// Draws the status message, cut to the width of the window
+    fn draw_status(&self) {
+        let width = self.window.get_max_x().max(0) as usize;
+        let status: String = self.status.chars().take(width).collect();
+        self.window.mvaddstr(STATUS_ROW, 0, status);
+    }
+
+    fn draw_hline(&self) {
+        let height = self.window.get_cur_y();
+        let width = self.window.get_max_x();
+        self.window.mv(height, 0);
+        for _ in 0..width {
+            self.window.addch('-');
+        }
+    }
+
+    fn clear_line(&self) {
+        let height = self.window.get_cur_y();
+        let width = self.window.get_max_x();
+        self.window.mv(height, 0);
+        for _ in 0..width {
+            self.window.addch(' ');
+        }
+    }
+
+    /// Exchanges the position of the active pane with `pane`
+    fn swap_pane(&mut self, pane: &mut Pane) {
+        std::mem::swap(&mut self.base_address, &mut pane.base_address);
+        std::mem::swap(&mut self.selected_address, &mut pane.selected_address);
+        std::mem::swap(&mut self.selection_start, &mut pane.selection_start);
+    }
+
+    /// Makes the inactive pane of the split view the active one
+    fn switch_pane(&mut self) {
+        if let Some(mut pane) = self.split_pane {
+            self.swap_pane(&mut pane);
+            self.split_pane = Some(pane);
+            self.split_pane_on_top = !self.split_pane_on_top;
+        }
+    }
+
+    /// Returns the span of bytes covered by the selection, from the start of its first item to
+    /// the end of its last one, None if no span is being selected
+    fn selection(&self) -> Option<(usize, usize)> {
+        let selection_start = self.selection_start?;
+        let start = selection_start.min(self.selected_address);
+        let last = selection_start.max(self.selected_address);
+        let end = self
+            .disassembler
+            .next_line(last, self.layout)
+            .min(self.disassembler.rom().len());
+        Some((start, end))
+    }
+
+    fn is_selected(&self, address: usize) -> bool {
+        match self.selection() {
+            Some((start, end)) => (start..end).contains(&address),
+            None => address == self.selected_address,
+        }
+    }
+
+    /// The number of rows drawn above the selected line when starting at `base_address`
+    fn rows_before_selection(&self) -> usize {
+        self.disassembler
+            .iter_with_layout(self.base_address, self.layout)
+            .take_while(|repr| match *repr {
+                AddressRepr::Label { address, .. } => address <= self.selected_address,
+                AddressRepr::Unknown { address, .. }
+                | AddressRepr::Data { address, .. }
+                | AddressRepr::Dump { address, .. }
+                | AddressRepr::Padding { address, .. }
+                | AddressRepr::Text { address, .. }
+                | AddressRepr::Pointer { address, .. }
+                | AddressRepr::Code { address, .. }
+                | AddressRepr::Undecoded { address, .. } => address < self.selected_address,
+            })
+            .count()
+    }
+
+    /// Records `address` as the newest entry of the follow stack. Entries are never dropped, so
+    /// following something after going back keeps the places that were gone back from, like
+    /// vim's jump list.
+    fn push_follow(&mut self, address: usize) {
+        self.follow_stack.retain(|&entry| entry != address);
+        self.follow_stack.push(address);
+        self.follow_stack_top = self.follow_stack.len();
+    }
+
+    /// Records the selected address before moving in the follow stack so that going forward
+    /// returns to it, unless an entry is already being visited
+    fn record_follow_position(&mut self) {
+        if self.follow_stack_top == self.follow_stack.len() {
+            self.push_follow(self.selected_address);
+            self.follow_stack_top -= 1;
+        }
+    }
+
+    fn follow_stack_previous(&mut self) -> Option<usize> {
+        self.record_follow_position();
+        if self.follow_stack_top == 0 {
+            return None;
+        }
+
+        self.follow_stack_top -= 1;
+        Some(self.follow_stack[self.follow_stack_top])
+    }
+
+    fn follow_stack_next(&mut self) -> Option<usize> {
+        if self.follow_stack_top + 1 >= self.follow_stack.len() {
+            return None;
+        }
+
+        self.follow_stack_top += 1;
+        Some(self.follow_stack[self.follow_stack_top])
+    }
+}
+
+/// Decoding, drawing and the commands that know about the Game Boy
+impl Application<GameBoy> {
     pub fn run(&mut self) {
         self.running = true;
         while self.running {
-            self.window.clear();
-            self.window.mv(0, 0);
-            self.draw_header();
-            self.window.mv(3, 0);
-            self.draw_hline();
-            self.draw_byte_store();
+            self.draw();
             self.handle_input();
             self.handle_type_changes();
+            // Everything a single command changed is undone at once
+            self.disassembler.commit_edits();
         }
     }
 
+    fn draw(&mut self) {
+        self.clamp_selection();
+        self.window.clear();
+        self.window.mv(0, 0);
+        self.draw_header();
+        self.draw_status();
+        self.window.mv(STATUS_ROW + 1, 0);
+        self.draw_hline();
+        self.draw_panes();
+    }
+
     fn handle_input(&mut self) {
         let input = self.window.getch();
-        self.window.mv(2, 0);
-        self.window.addstr(format!("{:?}", input));
+        // Messages stay until the next key, the command it runs may leave a new one
+        if matches!(input, Some(input) if input != Input::KeyResize) {
+            self.status.clear();
+        }
+        // Any key but a digit ends the count
+        let count = self.count.take();
         match input {
             None => {}
-            Some(Input::Character('\u{1b}')) | Some(Input::Character('q')) => self.running = false,
-            Some(Input::Character('j')) => {
-                self.selected_address = self.next_valid_address(self.selected_address)
+            Some(Input::KeyResize) => {
+                // The next frame is drawn with the new size, clamping the view to the new height
+                pancurses::resize_term(0, 0);
+                self.window.clear();
+            }
+            Some(Input::KeyMouse) => {
+                if let Ok(event) = pancurses::getmouse() {
+                    let clicked = pancurses::BUTTON1_CLICKED | pancurses::BUTTON1_PRESSED;
+                    if event.bstate & clicked != 0 {
+                        let line = self.line_rows.iter().find(|&&(row, ..)| row == event.y);
+                        if let Some(&(_, in_active_pane, address)) = line {
+                            if !in_active_pane {
+                                self.switch_pane();
+                            }
+                            self.selected_address = address;
+                        }
+                    }
+                }
             }
-            Some(Input::Character('k')) if self.selected_address > 0 => {
-                self.selected_address = self.snap_to_valid_address(self.selected_address - 1);
+            Some(Input::Character('\u{1b}')) if self.selection_start.is_some() => {
+                self.selection_start = None;
             }
-            Some(Input::Character('c')) => {
+            Some(Input::Character(digit @ '0'..='9'))
+                if (digit != '0' || count.is_some())
+                    && self.keys.action(Input::Character(digit)).is_none() =>
+            {
+                let digit = digit.to_digit(10).unwrap() as usize;
+                let count = count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+                self.count = Some(count);
+                self.status = count.to_string();
+            }
+            Some(input) => {
+                if let Some(action) = self.keys.action(input) {
+                    self.handle_action(action, count.unwrap_or(1));
+                }
+            }
+        }
+    }
+
+    /// Runs the command bound to a key, `count` times for motions
+    fn handle_action(&mut self, action: Action, count: usize) {
+        match action {
+            Action::Quit => self.running = false,
+            Action::StartSelection => self.selection_start = Some(self.selected_address),
+            Action::MoveDown => {
+                for _ in 0..count {
+                    let next = self
+                        .disassembler
+                        .next_line(self.selected_address, self.layout);
+                    // Stay on the last line
+                    if next >= self.disassembler.rom().len() {
+                        break;
+                    }
+                    self.selected_address = next;
+                }
+            }
+            Action::MoveUp => {
+                for _ in 0..count {
+                    if self.selected_address == 0 {
+                        break;
+                    }
+                    self.selected_address = self
+                        .disassembler
+                        .align_to_line(self.selected_address - 1, self.layout);
+                }
+            }
+            Action::MarkCode => {
                 self.type_changes
                     .push((ByteType::Code, self.selected_address));
             }
-            Some(Input::Character('d')) => {
+            Action::MarkData => match self.selection() {
+                Some((start, end)) => {
+                    for address in start..end {
+                        self.type_changes.push((ByteType::Data, address));
+                    }
+                    self.selection_start = None;
+                }
+                None => {
+                    self.type_changes
+                        .push((ByteType::Data, self.selected_address));
+                }
+            },
+            Action::MarkPointers => {
+                let (start, end) = self
+                    .selection()
+                    .unwrap_or((self.selected_address, self.selected_address + POINTER_SIZE));
+                for address in (start..end).step_by(POINTER_SIZE) {
+                    self.type_changes.push((ByteType::Pointer, address));
+                }
+                self.selection_start = None;
+            }
+            Action::MarkText => {
                 self.type_changes
-                    .push((ByteType::Data, self.selected_address));
+                    .push((ByteType::Text, self.selected_address));
+            }
+            Action::MarkDataLength => {
+                if let Some(length) = self.read_hex("Data length: ") {
+                    let end = (self.selected_address + length).min(self.disassembler.rom().len());
+                    for address in self.selected_address..end {
+                        self.type_changes.push((ByteType::Data, address));
+                    }
+                }
+            }
+            Action::GoToAddress => {
+                let input = match self.read_line("Go to address: ") {
+                    Some(input) => input,
+                    None => return,
+                };
+                match self.parse_goto_address(input.trim()) {
+                    Some(address) if address < self.disassembler.rom().len() => {
+                        self.push_follow(self.selected_address);
+                        self.base_address = address;
+                        self.selected_address = address;
+                    }
+                    Some(address) => {
+                        self.status = format!("{:x} is past the end of the ROM", address)
+                    }
+                    None => {
+                        if !input.is_empty() {
+                            self.status = format!("Invalid address: {}", input);
+                        }
+                    }
+                }
             }
-            Some(Input::Character('G')) => {
-                if let Ok(address) = usize::from_str_radix(&self.read_line("Go to address: "), 16) {
+            Action::SearchBytes => {
+                // Matches are highlighted as the pattern is typed
+                let pattern = self.read_line_with("Search bytes: ", |application, input| {
+                    application.search_highlight = parse_byte_pattern(input).unwrap_or_default();
+                    application.draw();
+                });
+                self.search_highlight.clear();
+                let pattern = match pattern {
+                    Some(pattern) => pattern,
+                    None => return,
+                };
+                match parse_byte_pattern(&pattern) {
+                    Some(pattern) => {
+                        match self
+                            .disassembler
+                            .find_bytes(&pattern, self.selected_address)
+                        {
+                            Some(address) => {
+                                self.push_follow(self.selected_address);
+                                self.base_address = address;
+                                self.selected_address = address;
+                            }
+                            None => self.status = "Pattern not found".to_string(),
+                        }
+                    }
+                    None => self.status = format!("Invalid byte pattern: {}", pattern),
+                }
+            }
+            Action::FindInstruction => {
+                let mnemonic = self.read_line("Find instruction: ").unwrap_or_default();
+                if !mnemonic.is_empty() {
+                    match self.find_instruction(&mnemonic) {
+                        Some(address) => {
+                            self.push_follow(self.selected_address);
+                            self.base_address = address;
+                            self.selected_address = address;
+                        }
+                        None => self.status = format!("No {} instruction found", mnemonic),
+                    }
+                }
+            }
+            Action::GoToLabel => {
+                let name = self.read_line("Go to label: ").unwrap_or_default();
+                if !name.is_empty() {
+                    self.go_to_label(&name);
+                }
+            }
+            Action::NextUnknown => match self.disassembler.find_unknown(self.selected_address) {
+                Some(address) => {
                     self.push_follow(self.selected_address);
                     self.base_address = address;
                     self.selected_address = address;
                 }
+                None => self.status = "No unknown bytes left".to_string(),
+            },
+            Action::FindFlagSetter => {
+                let tested_flag = self
+                    .instruction_at(self.selected_address)
+                    .and_then(|instruction| instruction.tested_flag());
+                let flag = match tested_flag {
+                    Some(flag) => flag,
+                    None => {
+                        self.status = "Not a conditional branch".to_string();
+                        return;
+                    }
+                };
+                match find_flag_setter(&self.disassembler, self.selected_address) {
+                    Some(address) => {
+                        let is_call = self
+                            .instruction_at(address)
+                            .is_some_and(|instruction| instruction.is_call());
+                        if is_call {
+                            self.status = format!("The {:?} flag may come from this call", flag);
+                        }
+                        self.push_follow(self.selected_address);
+                        self.base_address = address;
+                        self.selected_address = address;
+                    }
+                    None => {
+                        self.status = format!("Nothing running into the branch sets {:?}", flag);
+                    }
+                }
+            }
+            Action::Help => self.show_help(),
+            Action::NextLabel | Action::PreviousLabel => {
+                let address = if action == Action::NextLabel {
+                    self.disassembler.next_label(self.selected_address)
+                } else {
+                    self.disassembler.previous_label(self.selected_address)
+                };
+                match address {
+                    Some(address) => {
+                        self.base_address = address;
+                        self.selected_address = address;
+                    }
+                    None => self.status = "No more labels".to_string(),
+                }
             }
-            Some(Input::Character('f')) => {
-                if self.byte_store.types[self.selected_address] == ByteType::Code {
-                    if let Some(address) = self
-                        .instruction_at(self.selected_address)
-                        .and_then(|instruction| instruction.jump_address())
-                        .and_then(|address| {
-                            self.resolve_physical_address(self.selected_address, address)
-                                .get()
-                        })
+            Action::Follow
+                if self.disassembler.byte_type(self.selected_address) == ByteType::Code =>
+            {
+                let rom_length = self.disassembler.rom().len();
+                match self.follow_target(self.selected_address) {
+                    Some(address) if address < rom_length => {
+                        self.push_follow(self.selected_address);
+                        self.base_address = address;
+                        self.selected_address = address;
+                    }
+                    _ if self.instruction_at(self.selected_address)
+                        == Some(GBInstruction::JPHL) =>
                     {
+                        self.follow_jump_table();
+                    }
+                    _ => {
+                        let target = self
+                            .instruction_at(self.selected_address)
+                            .and_then(|instruction| instruction.jump_address())
+                            .map(|target| {
+                                self.resolve_physical_address(self.selected_address, target)
+                            });
+                        if let Some(ResolvedAddress::PastEnd(address)) = target {
+                            self.status = format!("{:06x} is past the end of the ROM", address);
+                        }
+                    }
+                }
+            }
+            Action::GoToOperand => {
+                let operand = self
+                    .instruction_at(self.selected_address)
+                    .and_then(|instruction| instruction.memory_operand());
+                let operand = match operand {
+                    Some(operand) => operand,
+                    None => {
+                        self.status = "No memory operand".to_string();
+                        return;
+                    }
+                };
+                match self.resolve_physical_address(self.selected_address, operand) {
+                    ResolvedAddress::Physical(address) => {
                         self.push_follow(self.selected_address);
                         self.base_address = address;
                         self.selected_address = address;
                     }
+                    ResolvedAddress::PastEnd(address) => {
+                        self.status = format!("{:06x} is past the end of the ROM", address)
+                    }
+                    ResolvedAddress::UnknownBank(offset) => {
+                        self.status = format!("The bank of {:04x} isn't known", offset | 0x4000)
+                    }
+                    ResolvedAddress::System(region, address) => {
+                        self.status = format!("{}:{:04x} isn't in the ROM", region, address)
+                    }
+                }
+            }
+            Action::Xrefs => self.choose_xref(),
+            Action::SetBookmark => {
+                if let Some(Input::Character(name)) = self.window.getch() {
+                    if name.is_ascii_alphabetic() {
+                        self.disassembler.set_bookmark(name, self.selected_address);
+                        self.status = format!("Bookmark {} set", name);
+                    }
+                }
+            }
+            Action::GoToBookmark => {
+                if let Some(Input::Character(name)) = self.window.getch() {
+                    let rom_length = self.disassembler.rom().len();
+                    match self.disassembler.bookmark(name) {
+                        Some(address) if address < rom_length => {
+                            self.push_follow(self.selected_address);
+                            self.base_address = address;
+                            self.selected_address = address;
+                        }
+                        Some(_) => {
+                            self.status = format!("Bookmark {} is past the end of the ROM", name)
+                        }
+                        None => self.status = format!("No bookmark {}", name),
+                    }
+                }
+            }
+            Action::Undo => {
+                if self.disassembler.undo() {
+                    self.rom_changed();
+                } else {
+                    self.status = "Nothing to undo".to_string();
+                }
+            }
+            Action::Redo => {
+                if self.disassembler.redo() {
+                    self.rom_changed();
+                } else {
+                    self.status = "Nothing to redo".to_string();
                 }
             }
-            Some(Input::Character('o')) => {
+            Action::ShowHeader => match &self.header {
+                Some(header) => self.show_lines("ROM header", &header.describe()),
+                None => self.status = "The ROM is too small to have a header".to_string(),
+            },
+            Action::FollowBack => {
                 if let Some(address) = self.follow_stack_previous() {
                     self.base_address = address;
                     self.selected_address = address;
                 }
             }
-            Some(Input::Character('i')) => {
+            Action::FollowForward => {
                 if let Some(address) = self.follow_stack_next() {
                     self.base_address = address;
                     self.selected_address = address;
                 }
             }
-            Some(Input::Character('b')) => {
-                if let Ok(bank) = usize::from_str_radix(&self.read_line("Bank number: "), 16) {
-                    self.banks.insert(self.selected_address, bank);
+            Action::SetBank => {
+                let bank_count = self.bank_count();
+                let prompt = format!("Bank number (1-{:x}): ", bank_count - 1);
+                if let Some(bank) = self.read_hex(&prompt) {
+                    if bank < bank_count {
+                        // A selection, e.g. a whole function body, gets the bank as a range
+                        match self.selection() {
+                            Some((start, end)) => {
+                                self.disassembler.set_bank_range(start, end, bank);
+                                self.selection_start = None;
+                            }
+                            None => self.disassembler.set_bank(self.selected_address, bank),
+                        }
+                        self.disassembler.update_xrefs();
+                    } else {
+                        self.status = format!(
+                            "Bank {:x} is out of range, the ROM has {:x} banks",
+                            bank, bank_count
+                        );
+                    }
+                }
+            }
+            Action::SetCurrentBank => {
+                let bank_count = self.bank_count();
+                let prompt = format!("Current bank (1-{:x}, empty for none): ", bank_count - 1);
+                let input = match self.read_line(&prompt) {
+                    Some(input) => input,
+                    None => return,
+                };
+                let bank = if input.is_empty() {
+                    None
+                } else {
+                    match usize::from_str_radix(&input, 16) {
+                        Ok(bank) if bank > 0 && bank < bank_count => Some(bank),
+                        Ok(bank) => {
+                            self.status = format!(
+                                "Bank {:x} is out of range, the ROM has {:x} banks",
+                                bank, bank_count
+                            );
+                            return;
+                        }
+                        Err(_) => {
+                            self.status = format!("Invalid bank: {}", input);
+                            return;
+                        }
+                    }
+                };
+                self.disassembler.set_current_bank(bank);
+            }
+            Action::Save => self.save_project(),
+            Action::ExportAsm => self.export_asm(),
+            Action::ExportSymbols => self.export_symbols(),
+            Action::ExportJson => self.export_json(),
+            Action::ExportCallGraph => self.export_call_graph(),
+            Action::SetComment => {
+                // Cancelling keeps the comment, an empty one removes it
+                if let Some(comment) = self.read_line("Comment: ") {
+                    self.disassembler
+                        .set_comment(self.selected_address, comment);
+                }
+            }
+            Action::SetLabel => {
+                if let Some(label) = self.read_line("Label (empty to remove): ") {
+                    self.set_label(self.selected_address, label);
+                }
+            }
+            Action::RenameLabel => {
+                let name = self.read_line("Rename label: ").unwrap_or_default();
+                if name.is_empty() {
+                    return;
+                }
+                match self.label_address(&name) {
+                    Some(address) => {
+                        let new_name = self
+                            .read_line(&format!("New name for {}: ", name))
+                            .unwrap_or_default();
+                        if !new_name.is_empty() {
+                            self.set_label(address, new_name);
+                        }
+                    }
+                    None => self.status = format!("No label named {}", name),
                 }
             }
-            Some(Input::Character('l')) => {
-                let label = self.read_line("label");
-                if !label.is_empty() {
-                    self.labels.insert(self.selected_address, label);
+            Action::ToggleSplit => {
+                self.split_pane = match self.split_pane {
+                    Some(_) => None,
+                    None => Some(Pane {
+                        base_address: self.base_address,
+                        selected_address: self.selected_address,
+                        selection_start: None,
+                    }),
+                };
+                self.split_pane_on_top = false;
+            }
+            Action::SwitchPane => self.switch_pane(),
+            Action::ToggleHexDump => {
+                self.layout = match self.layout {
+                    Layout::Listing => Layout::HexDump,
+                    Layout::HexDump => Layout::Listing,
+                };
+                self.selected_address = self
+                    .disassembler
+                    .align_to_line(self.selected_address, self.layout);
+            }
+            Action::ToggleRegisterNames => self.register_names = !self.register_names,
+            Action::Analyze => {
+                // The selected address is a start too, unless it has been marked as data
+                let target = match self.disassembler.byte_type(self.selected_address) {
+                    ByteType::Unknown | ByteType::Code => Some(self.selected_address),
+                    ByteType::Data | ByteType::Text | ByteType::Pointer => None,
+                };
+                let report = analyze_entry_points(&mut self.disassembler, target);
+                self.status = describe_code_report(&report, self.disassembler.rom());
+            }
+            Action::CycleImmediates => {
+                self.immediates = self.immediates.next();
+                let format = match self.immediates {
+                    Immediates::Hex => "hex",
+                    Immediates::Decimal => "decimal",
+                    Immediates::SignedDecimal => "signed decimal",
+                };
+                self.status = format!("Immediates shown in {}", format);
+            }
+            Action::ClearType => {
+                let (start, end) = self.selection().unwrap_or_else(|| {
+                    let end = self
+                        .disassembler
+                        .next_line(self.selected_address, self.layout);
+                    (self.selected_address, end)
+                });
+                self.selection_start = None;
+                self.clear_types(start, end);
+            }
+            Action::ToggleTimingMarks => self.timing_marks = !self.timing_marks,
+            Action::TogglePadding => {
+                match self.disassembler.toggle_padding(self.selected_address) {
+                    Some(start) => {
+                        self.selected_address = self.disassembler.align_to_line(start, self.layout);
+                    }
+                    None => {
+                        self.status = format!("No padding at {:06x}", self.selected_address);
+                    }
                 }
             }
-            Some(_) => {}
+            Action::ShowFollowStack => self.choose_follow_stack_entry(),
+            Action::PatchBytes => self.patch_bytes(),
+            Action::RevertPatches => self.revert_patches(),
+            Action::ShowPatches => self.choose_patch(),
+            Action::WritePatchedRom => self.write_patched_rom(),
+            Action::ExportIps => self.export_ips(),
+            _ => {}
         }
     }
 
     fn handle_type_changes(&mut self) {
-        while !self.type_changes.is_empty() {
-            self.handle_type_change();
+        if self.type_changes.is_empty() {
+            return;
+        }
+        while let Some((byte_type, address)) = self.type_changes.pop() {
+            match byte_type {
+                ByteType::Unknown => self.disassembler.mark_unknown(address),
+                ByteType::Data => self.disassembler.mark_data(address),
+                ByteType::Text => self.disassembler.mark_text(address),
+                ByteType::Pointer => self.disassembler.mark_pointer(address),
+                ByteType::Code => {
+                    let report = self.disassembler.mark_code(address);
+                    self.status = describe_code_report(&report, self.disassembler.rom());
+                }
+            }
         }
+        self.disassembler.update_xrefs();
     }
 
-    fn handle_type_change(&mut self) {
-        let (byte_type, mut address) = self.type_changes.pop().unwrap();
+    /// Sets the bytes from `start` to `end` back to unknown. The labels `mark_code` generated
+    /// for the cleared code that nothing references anymore can be removed along with it.
+    fn clear_types(&mut self, start: usize, end: usize) {
+        let mut generated_labels: Vec<usize> = (start..end)
+            .filter(|&address| self.disassembler.byte_type(address) == ByteType::Code)
+            .filter_map(|address| self.disassembler.branch_target(address))
+            .chain(start..end)
+            .collect();
+        for address in start..end {
+            self.disassembler.mark_unknown(address);
+        }
+        self.disassembler.update_xrefs();
+        self.status = format!("Cleared {} bytes", end - start);
 
-        self.byte_store.types[address] = byte_type;
+        generated_labels.sort_unstable();
+        generated_labels.dedup();
+        generated_labels.retain(|&address| {
+            self.disassembler.is_generated_label(address)
+                && self.disassembler.xrefs(address).is_empty()
+        });
+        if generated_labels.is_empty() {
+            return;
+        }
+        let question = format!(
+            "Remove the {} labels generated for the cleared code?",
+            generated_labels.len()
+        );
+        if self.confirm(&question) {
+            for &address in &generated_labels {
+                self.disassembler.set_label(address, String::new());
+            }
+            self.status += &format!(", removed {} labels", generated_labels.len());
+        }
+    }
 
-        if byte_type == ByteType::Code {
-            while let Some(instruction) =
-                GBInstruction::from_bytes(&self.byte_store.bytes[address..])
-            {
-                if let Some(unmapped_address) = instruction.jump_address() {
-                    if let Some(physical_address) = self
-                        .resolve_physical_address(address, unmapped_address)
-                        .get()
-                    {
-                        if self.byte_store.types[physical_address as usize] == ByteType::Unknown {
-                            self.type_changes.push((ByteType::Code, physical_address));
-                        }
+    fn export_asm(&mut self) {
+        self.status = match asm::export(&self.exports.asm, &self.disassembler) {
+            Ok(()) => format!("Exported {}", self.exports.asm.display()),
+            Err(error) => format!("Unable to export {}: {}", self.exports.asm.display(), error),
+        };
+    }
 
-                        if !self.labels.contains_key(&physical_address) {
-                            self.labels
-                                .insert(physical_address, format!("LOC_{:06X}", physical_address));
-                        }
-                    }
-                }
-                if !instruction.falls_through() {
-                    break;
-                }
-                address += instruction.size();
-                if self.byte_store.types[address] != ByteType::Unknown {
-                    break;
-                }
-                self.byte_store.types[address] = ByteType::Code;
-            }
+    /// Lets the user pick one of the instructions branching to the selected address and goes
+    /// there, or one of the instructions accessing it for bytes that aren't code
+    fn choose_xref(&mut self) {
+        let is_code = self.disassembler.byte_type(self.selected_address) == ByteType::Code;
+        let xrefs = if is_code {
+            self.disassembler.xrefs(self.selected_address).to_vec()
+        } else {
+            self.disassembler.data_xrefs(self.selected_address).to_vec()
+        };
+        if xrefs.is_empty() {
+            self.status = format!("No references to {:06x}", self.selected_address);
+            return;
+        }
+        let items: Vec<String> = xrefs
+            .iter()
+            .map(|&address| match self.instruction_at(address) {
+                Some(instruction) => format!(
+                    "{:06x}: {}",
+                    address,
+                    self.formatter().format_instruction(address, &instruction)
+                ),
+                None => format!("{:06x}", address),
+            })
+            .collect();
+        let title = if is_code {
+            format!("References to {:06x}", self.selected_address)
+        } else {
+            format!("Accesses to {:06x}", self.selected_address)
+        };
+        if let Some(index) = self.choose(&title, &items) {
+            self.push_follow(self.selected_address);
+            self.base_address = xrefs[index];
+            self.selected_address = xrefs[index];
         }
     }
 
-    fn read_line(&self, prompt: &str) -> String {
-        self.window.mvaddstr(1, 0, prompt);
-        pancurses::echo();
-        pancurses::nocbreak();
-        pancurses::curs_set(2);
-        let mut string = String::new();
-        let string = loop {
-            match self.window.getch() {
-                Some(Input::Character('\n')) => {
-                    break string;
-                }
-                Some(Input::Character(c)) => {
-                    string.push(c);
-                }
-                _ => {}
-            }
+    fn export_symbols(&mut self) {
+        self.status = match sym::export(&self.exports.sym, &self.disassembler) {
+            Ok(()) => format!("Exported {}", self.exports.sym.display()),
+            Err(error) => format!("Unable to export {}: {}", self.exports.sym.display(), error),
         };
-        pancurses::noecho();
-        pancurses::cbreak();
-        pancurses::curs_set(0);
+    }
 
-        self.window.mv(1, 0);
-        self.clear_line();
+    fn export_json(&mut self) {
+        self.status = match json::export(&self.exports.json, &self.disassembler) {
+            Ok(()) => format!("Exported {}", self.exports.json.display()),
+            Err(error) => format!(
+                "Unable to export {}: {}",
+                self.exports.json.display(),
+                error
+            ),
+        };
+    }
 
-        string
+    fn export_call_graph(&mut self) {
+        self.status = match dot::export(&self.exports.dot, &self.disassembler) {
+            Ok(()) => format!("Exported {}", self.exports.dot.display()),
+            Err(error) => format!("Unable to export {}: {}", self.exports.dot.display(), error),
+        };
+    }
+
+    /// The number of ROM banks, as given by the header but never more than the ROM file holds
+    fn bank_count(&self) -> usize {
+        let file_banks = self.disassembler.rom().len().div_ceil(BANK_SIZE).max(2);
+        self.header
+            .as_ref()
+            .and_then(RomHeader::rom_banks)
+            .map_or(file_banks, |banks| banks.min(file_banks))
     }
 
     fn instruction_at(&self, address: usize) -> Option<GBInstruction> {
-        GBInstruction::from_bytes(&self.byte_store.bytes[address..])
+        self.disassembler.instruction_at(address)
+    }
+
+    /// Returns the address of the next instruction after the selected one named `mnemonic`,
+    /// wrapping around at the end of the ROM. Only code and unknown bytes are searched. A
+    /// mnemonic without a condition also matches its conditional forms, e.g. `CALL` matches
+    /// `CALL NZ`.
+    fn find_instruction(&self, mnemonic: &str) -> Option<usize> {
+        let mnemonic = mnemonic.trim().to_uppercase();
+        let matches = |address: usize| {
+            let byte_type = self.disassembler.byte_type(address);
+            if byte_type != ByteType::Code && byte_type != ByteType::Unknown {
+                return false;
+            }
+            self.instruction_at(address).is_some_and(|instruction| {
+                let name = instruction.name();
+                name == mnemonic
+                    || name
+                        .strip_prefix(mnemonic.as_str())
+                        .is_some_and(|condition| condition.starts_with(' '))
+            })
+        };
+
+        let rom_length = self.disassembler.rom().len();
+        let mut address = self.selected_address;
+        let mut wrapped = false;
+        loop {
+            address = self.disassembler.next_valid_location(address);
+            if address >= rom_length {
+                if wrapped {
+                    return None;
+                }
+                wrapped = true;
+                address = 0;
+            }
+            if wrapped && address >= self.selected_address {
+                return matches(self.selected_address).then_some(self.selected_address);
+            }
+            if matches(address) {
+                return Some(address);
+            }
+        }
     }
 
     fn draw_header(&self) {
@@ -240,223 +1405,320 @@ impl Application {
             .addstr(format!("Address: {:04x}", self.selected_address));
 
         if let Some(instruction) = self.instruction_at(self.selected_address) {
-            self.window.addstr(format!(" {}", instruction.name()));
-            if let Some(first_argument) = instruction.first_argument() {
-                self.window.addstr(" ");
-                self.draw_argument(self.selected_address, &first_argument);
+            let text =
+                self.formatter()
+                    .mnemonic_with_operands(self.selected_address, &instruction, 0);
+            self.window.addstr(format!(" {}", text));
 
-                if let Some(second_argument) = instruction.second_argument() {
-                    self.window.addstr(", ");
-                    self.draw_argument(self.selected_address, &second_argument);
+            // Shown in clock cycles, as opcode tables usually do
+            let cycles = instruction.cycles();
+            match cycles.taken {
+                Some(taken) => {
+                    self.window
+                        .addstr(format!(" [{}t/{}t]", cycles.base * 4, taken * 4))
                 }
+                None => self.window.addstr(format!(" [{}t]", cycles.base * 4)),
+            };
+
+            let flags = instruction.flags_affected();
+            if !flags.is_none() {
+                self.window.addstr(format!(" [{}]", flags));
             }
 
-            if self.byte_store.types[self.selected_address] != ByteType::Code {
+            if self.disassembler.byte_type(self.selected_address) != ByteType::Code {
                 self.window.addstr(" [c]ode");
             }
 
-            if let Some(address) = instruction.jump_address().and_then(|address| {
-                self.resolve_physical_address(self.selected_address, address)
-                    .get()
-            }) {
-                self.window.addstr(format!(" [f]ollow ({:04x})", address));
+            if let Some(address) = self.follow_target(self.selected_address) {
+                match self.disassembler.label(address) {
+                    Some(label) => self.window.addstr(format!(" [f]ollow ({})", label)),
+                    None => self.window.addstr(format!(" [f]ollow ({:04x})", address)),
+                };
             }
         }
 
-        if self.byte_store.types[self.selected_address] != ByteType::Data {
+        if self.disassembler.byte_type(self.selected_address) != ByteType::Data {
             self.window.addstr(" [d]ata");
         }
         self.window.addstr(" [G]oto [b]ank");
+        if let Some(bank) = self.disassembler.current_bank() {
+            self.window.addstr(format!(" [in bank {:x}]", bank));
+        }
+
+        // Analysis progress, on the right if there is room left
+        let coverage = self.disassembler.coverage();
+        let total = self.disassembler.rom().len().max(1) as f64;
+        let progress = format!(
+            "Code {:.1}% Data {:.1}% Unknown {:.1}%",
+            coverage.code as f64 * 100.0 / total,
+            coverage.data as f64 * 100.0 / total,
+            coverage.unknown as f64 * 100.0 / total
+        );
+        let column = self.window.get_max_x() - progress.len() as i32;
+        if column > self.window.get_cur_x() {
+            self.window.mvaddstr(0, column, progress);
+        }
     }
 
-    fn draw_hline(&self) {
-        let height = self.window.get_cur_y();
-        let width = self.window.get_max_x();
-        self.window.mv(height, 0);
-        for _ in 0..width {
-            self.window.addch('-');
+    /// Keeps the selected address in the ROM before anything is drawn from it. Moves can step
+    /// past the end of an instruction cut by the end of the ROM, the last line is selected
+    /// instead. The inactive pane was clamped when it was the active one.
+    fn clamp_selection(&mut self) {
+        let rom_length = self.disassembler.rom().len();
+        if self.selected_address >= rom_length {
+            self.selected_address = self.disassembler.align_to_line(rom_length - 1, self.layout);
+        }
+        if let Some(start) = self.selection_start {
+            self.selection_start = Some(start.min(rom_length - 1));
         }
     }
 
-    fn clear_line(&self) {
-        let height = self.window.get_cur_y();
-        let width = self.window.get_max_x();
-        self.window.mv(height, 0);
-        for _ in 0..width {
-            self.window.addch(' ');
+    /// Draws the ROM under the header, in two panes separated by a line when the view is split
+    fn draw_panes(&mut self) {
+        self.line_rows.clear();
+        let bottom = self.window.get_max_y();
+        if self.split_pane.is_none() {
+            self.draw_byte_store(bottom, true);
+            return;
+        }
+
+        let y0 = self.window.get_cur_y();
+        let middle = y0 + (bottom - y0) / 2;
+        self.draw_pane(!self.split_pane_on_top, middle);
+        self.window.mv(middle, 0);
+        self.draw_hline();
+        self.window.mv(middle + 1, 0);
+        self.draw_pane(self.split_pane_on_top, bottom);
+    }
+
+    fn draw_pane(&mut self, is_active: bool, bottom: i32) {
+        if is_active {
+            self.draw_byte_store(bottom, true);
+        } else if let Some(mut pane) = self.split_pane {
+            // Drawn with the inactive pane's position, which keeps how it is scrolled
+            self.swap_pane(&mut pane);
+            self.draw_byte_store(bottom, false);
+            self.swap_pane(&mut pane);
+            self.split_pane = Some(pane);
         }
     }
 
-    fn draw_byte_store(&mut self) {
+    /// Draws the lines from `base_address` down to the row before `bottom`, `is_active` tells
+    /// whether the lines belong to the active pane
+    fn draw_byte_store(&mut self, bottom: i32, is_active: bool) {
         let y0 = self.window.get_cur_y();
-        let height = (self.window.get_max_y() - 1 - y0) as usize;
+        let height = (bottom - 1 - y0).max(0) as usize;
         if self.selected_address < self.base_address {
             self.base_address = self.selected_address;
         }
         if self.selected_address > self.base_address + height {
-            self.base_address = self.selected_address - height - 1;
+            self.base_address = self.selected_address - height;
+        }
+        self.base_address = self
+            .disassembler
+            .align_to_line(self.base_address, self.layout);
+        // Labels take a row of their own, keep scrolling until the selected line fits
+        while self.base_address < self.selected_address && self.rows_before_selection() > height {
+            self.base_address = self.disassembler.next_line(self.base_address, self.layout);
         }
 
         self.window.mv(y0, 0);
-        let mut offset = 0usize;
-        loop {
-            if self.base_address + offset >= self.byte_store.bytes.len() {
-                break;
+        for repr in self
+            .disassembler
+            .iter_with_layout(self.base_address, self.layout)
+        {
+            let row = self.window.get_cur_y();
+            let (line_address, bytes) = match listing::line_start(&repr) {
+                Some(start) => start,
+                None => {
+                    if let AddressRepr::Label { address, name } = repr {
+                        self.line_rows.push((row, is_active, address));
+                        self.window.attrset(
+                            pancurses::A_BOLD | pancurses::COLOR_PAIR(LABEL_COLOR as chtype),
+                        );
+                        self.window.addstr(format!("{}:\n", name));
+                    }
+                    continue;
+                }
+            };
+            let highlighted = !self.search_highlight.is_empty() && {
+                let line_end = self.disassembler.next_line(line_address, self.layout);
+                (line_address..line_end)
+                    .any(|start| self.disassembler.bytes_match(&self.search_highlight, start))
+            };
+            self.draw_line_start(line_address, highlighted);
+            if !bytes.is_empty() {
+                self.draw_bytes(line_address, bytes);
             }
+            self.window
+                .addstr(self.formatter().format_line(&repr).unwrap_or_default());
 
-            let line_address = self.base_address + offset;
+            self.line_rows.push((row, is_active, line_address));
 
-            if let Some(label) = self.labels.get(&line_address) {
-                self.window.addstr(format!("{}:\n", label));
+            if let Some(comment) = self.disassembler.comment(line_address) {
+                self.window.addstr(format!(" ; {}", comment));
             }
 
-            if line_address == self.selected_address {
-                self.window.attron(pancurses::A_REVERSE);
-            } else {
-                self.window.attroff(pancurses::A_REVERSE);
-            }
-
-            let byte = self.byte_store.bytes[line_address as usize];
-            let byte_type = self.byte_store.types[line_address as usize];
-            self.window.addstr(format!("{:06x}: ", line_address));
-
-            match byte_type {
-                ByteType::Unknown => {
-                    self.window.addstr(format!("{:02x}", byte));
-                    offset += 1;
-                    self.window.mv(self.window.get_cur_y(), 20);
-                    self.window.addstr("??");
-                }
-                ByteType::Data => {
-                    self.window.addstr(format!("{:02x}", byte));
-                    offset += 1;
-                    self.window.mv(self.window.get_cur_y(), 20);
-                    self.window.addstr("db");
-                }
-                ByteType::Code => {
-                    if let Some(instr) =
-                        GBInstruction::from_bytes(&self.byte_store.bytes[line_address..])
-                    {
-                        for byte_index in 0..instr.size() {
-                            let byte = self.byte_store.bytes[line_address + byte_index];
-                            self.window.addstr(format!("{:02x} ", byte));
-                        }
-                        self.window.mv(self.window.get_cur_y(), 20);
-                        self.draw_instruction(line_address, &instr);
-                        offset += instr.size();
-                    } else {
-                        self.window.addstr(format!("{:02x}", byte));
-                        offset += 1;
-                        self.window.mv(self.window.get_cur_y(), 20);
-                        self.window.addstr("Illegal instruction");
+            if self.timing_marks {
+                if let AddressRepr::Code { instruction, .. } = repr {
+                    if let Some(register) = lcd_timing_register(&instruction) {
+                        self.draw_margin_mark(&format!("[{}]", register));
                     }
                 }
             }
 
-            if line_address == self.selected_address {
+            if self.is_selected(line_address) {
                 let width = self.window.get_max_x();
                 self.window.chgat(width, pancurses::A_REVERSE, 0);
             }
 
-            if self.window.get_cur_y() < self.window.get_max_y() - 1 {
+            if self.window.get_cur_y() < bottom - 1 {
                 self.window.mv(self.window.get_cur_y() + 1, 0);
             } else {
                 break;
             }
         }
+        self.window.attrset(pancurses::A_NORMAL);
     }
 
-    fn draw_instruction(&self, read_at: usize, instruction: &GBInstruction) {
-        let base_x = self.window.get_cur_x();
-        self.window.addstr(instruction.name());
-        if let Some(first_argument) = instruction.first_argument() {
-            self.window.mv(self.window.get_cur_y(), base_x + 6);
-            self.draw_argument(read_at, &first_argument);
-
-            if let Some(second_argument) = instruction.second_argument() {
-                self.window.addstr(", ");
-                self.draw_argument(read_at, &second_argument);
+    /// Draws the bytes of a line, only the first ones if they don't fit before the mnemonic
+    /// column, then moves to that column
+    fn draw_bytes(&self, address: usize, bytes: &[u8]) {
+        let text = listing::format_bytes(bytes);
+        // Each byte takes 3 columns, the patched ones stand out
+        for (index, byte_text) in text.as_bytes().chunks(3).enumerate() {
+            let byte_text = std::str::from_utf8(byte_text).unwrap_or_default();
+            if index < bytes.len() && self.disassembler.is_patched(address + index) {
+                let attributes = self.window.attrget().0;
+                self.window
+                    .attrset(pancurses::A_BOLD | pancurses::COLOR_PAIR(PATCH_COLOR as chtype));
+                self.window.addstr(byte_text);
+                self.window.attrset(attributes);
+            } else {
+                self.window.addstr(byte_text);
             }
         }
+        self.window
+            .mv(self.window.get_cur_y(), MNEMONIC_COLUMN as i32);
     }
 
-    fn draw_argument(&self, read_at: usize, argument: &Argument) {
-        match argument {
-            &Argument::Imm8(value) => {
-                self.window.addstr(format!("{:02x}", value));
-            }
-            &Argument::Imm16(value) => {
-                self.window.addstr(format!("{:04x}", value));
-            }
-            &Argument::Rel8(value) => {
-                self.window
-                    .addstr(format!("({:04x})", read_at.wrapping_add(value as usize)));
-            }
-            &Argument::Reg8(register) => {
-                self.window.addstr(format!("{}", register));
+    /// Draws `mark` at the right end of the current row, leaving the cursor where it was
+    fn draw_margin_mark(&self, mark: &str) {
+        let (y, x) = self.window.get_cur_yx();
+        let column = self.window.get_max_x() - mark.len() as i32;
+        if column > x {
+            self.window.attron(pancurses::A_BOLD);
+            self.window.mvaddstr(y, column, mark);
+            self.window.attroff(pancurses::A_BOLD);
+            self.window.mv(y, x);
+        }
+    }
+
+    /// Sets the highlight for the line at `address` and draws its address column. `highlighted`
+    /// lines hold a match of the search being typed.
+    fn draw_line_start(&self, address: usize, highlighted: bool) {
+        let is_target = self.disassembler.branch_target(self.selected_address) == Some(address);
+        let color = if is_target {
+            TARGET_COLOR
+        } else {
+            match self.disassembler.byte_type(address) {
+                ByteType::Code => CODE_COLOR,
+                ByteType::Data | ByteType::Text | ByteType::Pointer => DATA_COLOR,
+                ByteType::Unknown => 0,
             }
-            &Argument::Reg16(register) => {
-                self.window.addstr(format!("{}", register));
+        };
+        let mut attributes = pancurses::COLOR_PAIR(color as chtype);
+        if self.disassembler.byte_type(address) == ByteType::Unknown {
+            attributes |= pancurses::A_DIM;
+        }
+        if self.is_selected(address) {
+            attributes |= pancurses::A_REVERSE;
+        }
+        if highlighted {
+            attributes |= pancurses::A_BOLD | pancurses::A_UNDERLINE;
+        }
+        self.window.attrset(attributes);
+        // Point at where the selected instruction branches to
+        if is_target {
+            self.window.attron(pancurses::A_BOLD);
+            self.window.addstr(format!("{:06x}> ", address));
+            self.window.attroff(pancurses::A_BOLD);
+        } else {
+            self.window.addstr(format!("{:06x}: ", address));
+        }
+    }
+
+    /// Interprets the jump table used by the `JP HL` at the selected address. The table is
+    /// looked for in a `LD HL` shortly before it, the user is asked for its address otherwise.
+    /// Its entries are marked as pointers and their targets as code.
+    fn follow_jump_table(&mut self) {
+        let load_address = (1..=16)
+            .filter_map(|back_offset| self.selected_address.checked_sub(back_offset))
+            .filter(|&address| self.disassembler.byte_type(address) == ByteType::Code)
+            .find_map(|address| match self.instruction_at(address) {
+                Some(GBInstruction::LDd16(Reg16::HL, value)) => Some((address, value)),
+                _ => None,
+            });
+        let table = match load_address {
+            Some((address, value)) => self
+                .resolve_physical_address(address, UnmappedAddress(value))
+                .get(),
+            None => self.read_hex("Jump table address: "),
+        };
+        let table = match table {
+            Some(table) if table < self.disassembler.rom().len() => table,
+            _ => {
+                self.status = "Unable to locate the jump table".to_string();
+                return;
             }
-            &Argument::Address(unmapped_address) => {
-                match self.resolve_physical_address(read_at, unmapped_address) {
-                    ResolvedAddress::Physical(address) => {
-                        if let Some(label) = self.labels.get(&address) {
-                            self.window.addstr(label);
-                        } else {
-                            self.window.addstr(format!("({:06x})", address));
-                        }
-                    }
-                    ResolvedAddress::UnknownBank(offset) => {
-                        self.window.addstr(format!("(??:{:04x})", offset));
-                    }
-                    ResolvedAddress::System(address) => {
-                        let name = match address {
-                            _ => format!("(SYS:{:04x})", address),
-                        };
-                        self.window.addstr(name);
-                    }
+        };
+
+        let prompt = format!("Jump table at {:06x}, number of entries: ", table);
+        let entries = match self.read_hex(&prompt) {
+            Some(entries) => entries,
+            None => return,
+        };
+        for entry in 0..entries {
+            let address = table + entry * POINTER_SIZE;
+            let pointer = match self.disassembler.rom().get(address..address + POINTER_SIZE) {
+                Some(&[low, high]) => u16::from_le_bytes([low, high]),
+                _ => break,
+            };
+            self.disassembler.mark_pointer(address);
+            if let Some(target) = self
+                .resolve_physical_address(address, UnmappedAddress(pointer))
+                .get()
+            {
+                if self.disassembler.label(target).is_none() {
+                    self.disassembler
+                        .set_label(target, format!("LOC_{:06X}", target));
                 }
-            }
-            &Argument::IndirectReg16(register) => {
-                self.window.addstr(format!("({})", register));
-            }
-            &Argument::IndirectHLinc => {
-                self.window.addstr("(HL+)");
-            }
-            &Argument::IndirectHLdec => {
-                self.window.addstr("(HL-)");
-            }
-            &Argument::IndirectC => {
-                self.window.addstr("(SYS:ff00 + C)");
-            }
-            &Argument::ResetVector(reset_vector) => {
-                self.window.addstr(format!("{}", reset_vector));
+                self.disassembler.mark_code(target);
             }
         }
+        self.disassembler.update_xrefs();
+
+        self.push_follow(self.selected_address);
+        self.base_address = table;
+        self.selected_address = table;
     }
 
-    fn snap_to_valid_address(&self, address: usize) -> usize {
-        for backoffset in 1..3.min(address + 1) {
-            let offset_address = address - backoffset;
-            if self.byte_store.types[offset_address] == ByteType::Code {
-                if let Some(instruction) = self.instruction_at(offset_address) {
-                    if instruction.size() > backoffset {
-                        return offset_address;
-                    }
-                }
-            }
+    /// Returns where `f` goes from the instruction at `address`
+    fn follow_target(&self, address: usize) -> Option<usize> {
+        let instruction = self.instruction_at(address)?;
+        match instruction.jump_address() {
+            Some(jump_address) => self.resolve_physical_address(address, jump_address).get(),
+            // Relative jumps don't depend on banks
+            None => self.disassembler.branch_target(address),
         }
-        return address;
     }
 
-    fn next_valid_address(&self, address: usize) -> usize {
-        if self.byte_store.types[address] == ByteType::Code {
-            if let Some(instruction) = self.instruction_at(address) {
-                return address + instruction.size();
-            }
+    fn formatter(&self) -> Formatter<'_> {
+        Formatter {
+            disassembler: &self.disassembler,
+            register_names: self.register_names,
+            immediates: self.immediates,
         }
-        return address + 1;
     }
 
     fn resolve_physical_address(
@@ -464,70 +1726,371 @@ impl Application {
         read_at: usize,
         address: UnmappedAddress,
     ) -> ResolvedAddress {
-        if address.0 < 0x4000 {
-            return ResolvedAddress::Physical(address.0 as usize);
-        } else if address.0 < 0x8000 {
-            let offset = (address.0 & 0x3fff) as usize;
-            if read_at >= 0x4000 && read_at < 0x8000 {
-                // We're already in the bank, so we know its number
-                let bank = read_at / 0x4000;
-                return ResolvedAddress::Physical(bank * 0x4000 + offset);
-            }
-            if let Some(bank) = self.banks.get(&read_at) {
-                return ResolvedAddress::Physical(bank * 0x4000 + offset);
-            } else {
-                return ResolvedAddress::UnknownBank(address.0 & 0x3fff);
-            }
-        } else {
-            return ResolvedAddress::System(address.0);
+        gb::resolve_physical_address(&self.disassembler, read_at, address)
+    }
+
+    /// Lists the follow stack, newest first, and goes to the chosen entry
+    fn choose_follow_stack_entry(&mut self) {
+        if self.follow_stack.is_empty() {
+            self.status = "The follow stack is empty".to_string();
+            return;
+        }
+        self.record_follow_position();
+        let items: Vec<String> = self
+            .follow_stack
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, &address)| {
+                let marker = if index == self.follow_stack_top {
+                    '>'
+                } else {
+                    ' '
+                };
+                let description = match (
+                    self.disassembler.label(address),
+                    self.instruction_at(address),
+                ) {
+                    (Some(label), _) => label.to_string(),
+                    (None, Some(instruction)) => {
+                        self.formatter().format_instruction(address, &instruction)
+                    }
+                    (None, None) => String::new(),
+                };
+                format!("{} {:06x}: {}", marker, address, description)
+            })
+            .collect();
+        if let Some(index) = self.choose("Follow stack", &items) {
+            self.follow_stack_top = self.follow_stack.len() - 1 - index;
+            let address = self.follow_stack[self.follow_stack_top];
+            self.base_address = address;
+            self.selected_address = address;
         }
     }
+}
 
-    fn push_follow(&mut self, address: usize) {
-        if self.follow_stack_top == self.follow_stack.len() {
-            self.follow_stack.push(address);
-        } else {
-            self.follow_stack[self.follow_stack_top] = address;
+impl<Arch: Frontend> Application<Arch> {
+    /// Adds the labels from the symbol file at `path`
+    pub fn import_symbols(&mut self, path: &Path) {
+        self.status = match Arch::import_symbols(path, &mut self.disassembler) {
+            Ok(count) => format!("Imported {} symbols from {}", count, path.display()),
+            Err(error) => format!("Unable to import {}: {}", path.display(), error),
+        };
+    }
+}
+
+impl<Arch: Architecture> Drop for Application<Arch> {
+    fn drop(&mut self) {
+        pancurses::endwin();
+    }
+}
+
+/// What the viewer and the dump need from an architecture besides the disassembly
+trait Frontend: Architecture + Sized {
+    /// Marks the code reachable from where the hardware starts running, for new projects
+    fn analyze_new_project(disassembler: &mut Disassembler<Self>);
+
+    /// Where the program proper starts, shown first rather than the start of the ROM
+    fn main_program(disassembler: &Disassembler<Self>) -> Option<usize>;
+
+    fn import_symbols(path: &Path, disassembler: &mut Disassembler<Self>) -> io::Result<usize>;
+
+    fn print_listing(out: &mut impl Write, disassembler: &Disassembler<Self>) -> io::Result<()>;
+
+    /// Runs the viewer until the user quits
+    fn view(application: &mut Application<Self>);
+}
+
+impl Frontend for GameBoy {
+    fn analyze_new_project(disassembler: &mut Disassembler<GameBoy>) {
+        analyze_entry_points(disassembler, None);
+    }
+
+    fn main_program(disassembler: &Disassembler<GameBoy>) -> Option<usize> {
+        entry_jump_target(disassembler)
+    }
+
+    fn import_symbols(path: &Path, disassembler: &mut Disassembler<GameBoy>) -> io::Result<usize> {
+        sym::import(path, disassembler)
+    }
+
+    fn print_listing(out: &mut impl Write, disassembler: &Disassembler<GameBoy>) -> io::Result<()> {
+        let formatter = Formatter {
+            disassembler,
+            register_names: true,
+            immediates: Immediates::Hex,
+        };
+        listing::print(out, &formatter)
+    }
+
+    fn view(application: &mut Application<GameBoy>) {
+        application.run();
+    }
+}
+
+/// Summarizes what marking code did, e.g. `Marked 12 bytes as code, 2 new labels, stopped at
+/// illegal opcode at 000153`
+fn describe_code_report(report: &CodeReport, rom: &[u8]) -> String {
+    let mut summary = format!(
+        "Marked {} bytes as code, {} new labels",
+        report.bytes, report.labels
+    );
+    let stops = [
+        ("illegal opcode", &report.illegal),
+        ("instruction cut by the end of the ROM", &report.truncated),
+        ("padding", &report.traps),
+        ("data", &report.data_overlaps),
+    ];
+    for (reason, addresses) in stops.iter() {
+        if let Some(&first) = addresses.first() {
+            summary += &format!(
+                ", stopped at {} at {:06x} (byte {:02x})",
+                reason, first, rom[first]
+            );
+            if addresses.len() > 1 {
+                summary += &format!(" (and {} more)", addresses.len() - 1);
+            }
         }
-        self.follow_stack_top += 1;
     }
+    summary
+}
 
-    fn follow_stack_previous(&mut self) -> Option<usize> {
-        if self.follow_stack_top == 0 {
-            return None;
+/// Parses a string of hex bytes such as `cd 34 12`, `??` matches any byte
+fn parse_byte_pattern(pattern: &str) -> Option<Vec<Option<u8>>> {
+    let digits: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            if pair == "??" {
+                Some(None)
+            } else {
+                u8::from_str_radix(&pair, 16).ok().map(Some)
+            }
+        })
+        .collect()
+}
+
+/// Prints the listing of the ROM for `--dump`, annotated like it would be in the viewer
+fn dump<Arch: Frontend>(
+    rom_data: Vec<u8>,
+    project_path: &Path,
+    new_project: bool,
+    sym_import: Option<PathBuf>,
+) {
+    let mut disassembler = Disassembler::<Arch>::new(rom_data);
+    if !new_project {
+        if let Err(error) = project::load(project_path, &mut disassembler) {
+            eprintln!("Unable to load {}: {}", project_path.display(), error);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = sym_import {
+        if let Err(error) = Arch::import_symbols(&path, &mut disassembler) {
+            eprintln!("Unable to import {}: {}", path.display(), error);
+            std::process::exit(1);
         }
+    }
+    if new_project {
+        Arch::analyze_new_project(&mut disassembler);
+    }
+    disassembler.update_xrefs();
 
-        self.follow_stack_top -= 1;
-        Some(self.follow_stack[self.follow_stack_top])
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match Arch::print_listing(&mut out, &disassembler).and_then(|()| out.flush()) {
+        // The output was piped to something like `head` that didn't read it all
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(error) => {
+            eprintln!("Unable to write the listing: {}", error);
+            std::process::exit(1);
+        }
+        Ok(()) => {}
     }
+}
 
-    fn follow_stack_next(&mut self) -> Option<usize> {
-        if self.follow_stack_top == self.follow_stack.len() {
-            return None;
+/// How the ROM is opened, from the command line
+struct Options {
+    project_path: PathBuf,
+    /// Whether the project doesn't exist yet
+    new_project: bool,
+    exports: ExportPaths,
+    sym_import: Option<PathBuf>,
+    keys_path: Option<PathBuf>,
+    /// Whether to print the listing instead of starting the viewer
+    dump: bool,
+    from_stdin: bool,
+}
+
+/// Opens the ROM as code for `Arch`, in the viewer or printing its listing
+fn run<Arch: Frontend>(rom_data: Vec<u8>, options: Options) {
+    if options.dump {
+        dump::<Arch>(
+            rom_data,
+            &options.project_path,
+            options.new_project,
+            options.sym_import,
+        );
+        return;
+    }
+    if options.from_stdin {
+        reopen_terminal();
+    }
+    let mut application: Application<Arch> =
+        Application::new(rom_data, options.project_path, options.exports);
+    application.load_project();
+    if let Some(path) = options.sym_import {
+        application.import_symbols(&path);
+    }
+    if options.new_project {
+        Arch::analyze_new_project(&mut application.disassembler);
+    }
+    if let Some(path) = options.keys_path {
+        application.load_keys(&path);
+    }
+    // Start on the main program rather than on the reset vectors, unless the last session
+    // says otherwise
+    if let Some(target) = Arch::main_program(&application.disassembler) {
+        if application.disassembler.byte_type(target) == ByteType::Unknown {
+            application.disassembler.mark_code(target);
         }
+        application.base_address = target;
+        application.selected_address = target;
+    }
+    application.load_session();
+    application.disassembler.clear_history();
+    Arch::view(&mut application);
 
-        let address = self.follow_stack[self.follow_stack_top];
-        self.follow_stack_top += 1;
-        Some(address)
+    let session_path = application.session_path.clone();
+    let saved = application.save_session();
+    // Restore the terminal before reporting the error
+    drop(application);
+    if let Err(error) = saved {
+        eprintln!("Unable to save {}: {}", session_path.display(), error);
     }
 }
 
-impl Drop for Application {
-    fn drop(&mut self) {
-        pancurses::endwin();
+/// Makes the terminal the standard input again once a piped ROM has been read from it, as
+/// curses reads the keys from there
+#[cfg(unix)]
+fn reopen_terminal() {
+    use std::os::unix::io::AsRawFd;
+
+    let terminal = File::open("/dev/tty").unwrap_or_else(|error| {
+        eprintln!("Unable to open the terminal: {}", error);
+        std::process::exit(1);
+    });
+    // SAFETY: both descriptors are open, the terminal is duplicated rather than moved
+    if unsafe { libc::dup2(terminal.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        eprintln!(
+            "Unable to read keys from the terminal: {}",
+            io::Error::last_os_error()
+        );
+        std::process::exit(1);
     }
 }
 
+/// The console is read directly, whatever the standard input is
+#[cfg(not(unix))]
+fn reopen_terminal() {}
+
 fn main() {
     let matches = clap::App::new("gbretools")
-        .arg(clap::Arg::with_name("rom_file").required(true))
+        .arg(
+            clap::Arg::with_name("rom_file")
+                .required(true)
+                .help("The ROM to disassemble, - to read it from the standard input"),
+        )
+        .arg(
+            clap::Arg::with_name("arch")
+                .long("arch")
+                .takes_value(true)
+                .possible_values(&[GameBoy::NAME])
+                .default_value(GameBoy::NAME)
+                .help("The processor the ROM is for"),
+        )
+        .arg(
+            clap::Arg::with_name("project")
+                .long("project")
+                .takes_value(true)
+                .help("The file annotations are loaded from and saved to, <rom>.retool by default"),
+        )
+        .arg(
+            clap::Arg::with_name("keys")
+                .long("keys")
+                .takes_value(true)
+                .help("The key bindings file, ~/.config/retool/keys by default"),
+        )
+        .arg(
+            clap::Arg::with_name("dump")
+                .long("dump")
+                .alias("print")
+                .help(
+                    "Prints the disassembly to the standard output instead of starting the viewer",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("sym")
+                .long("sym")
+                .takes_value(true)
+                .help("Imports the labels of a .sym file, <rom>.sym is used for new projects"),
+        )
         .get_matches();
 
     let filename = matches.value_of("rom_file").unwrap();
-    let mut rom_file = File::open(filename).expect(&format!("Unable to open file {}", filename));
+    let from_stdin = filename == "-";
     let mut rom_data = Vec::new();
-    rom_file.read_to_end(&mut rom_data).unwrap();
+    if from_stdin {
+        io::stdin()
+            .read_to_end(&mut rom_data)
+            .unwrap_or_else(|error| panic!("Unable to read the standard input: {}", error));
+    } else {
+        let mut rom_file =
+            File::open(filename).unwrap_or_else(|_| panic!("Unable to open file {}", filename));
+        rom_file.read_to_end(&mut rom_data).unwrap();
+    }
+    if rom_data.is_empty() {
+        eprintln!(
+            "{} is empty",
+            if from_stdin { "The input" } else { filename }
+        );
+        std::process::exit(1);
+    }
+    // The other files are named after the ROM, a piped ROM has no name to go by
+    let filename = if from_stdin { "stdin" } else { filename };
+
+    let project_path = match matches.value_of("project") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!("{}.retool", filename)),
+    };
+    let exports = ExportPaths::new(Path::new(filename));
 
-    let mut application = Application::new(rom_data);
-    application.run();
+    let new_project = !project_path.exists();
+    let sym_import = match matches.value_of("sym") {
+        Some(path) => Some(PathBuf::from(path)),
+        None if new_project && exports.sym.exists() => Some(exports.sym.clone()),
+        None => None,
+    };
+    let keys_path = match matches.value_of("keys") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config/retool/keys"))
+            .filter(|path| path.exists()),
+    };
+    let options = Options {
+        project_path,
+        new_project,
+        exports,
+        sym_import,
+        keys_path,
+        dump: matches.is_present("dump"),
+        from_stdin,
+    };
+    match matches.value_of("arch").unwrap() {
+        GameBoy::NAME => run::<GameBoy>(rom_data, options),
+        arch => unreachable!("clap accepted the unknown architecture {}", arch),
+    }
 }
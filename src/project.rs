@@ -0,0 +1,330 @@
+//! Saving and loading of the annotations made on a ROM.
+//!
+//! Projects are stored in a simple line-based text format:
+//!
+//! ```text
+//! type code 000100 4
+//! label 000150 Main
+//! comment 000150 Waits for VBlank
+//! bookmark a 000150
+//! bank 000153 3
+//! bank-range 004000 1a0 3
+//! patch 000151 03
+//! follow 000100
+//! ```
+//!
+//! Addresses, lengths and bank numbers are in hexadecimal. Byte types are stored as runs of
+//! consecutive bytes sharing the same type, unknown bytes are not stored. `bank-range` lines give
+//! the bank of a whole span of instructions, as its start, length and bank. `patch` lines give
+//! the value written over a byte of the ROM. `follow` lines are the entries of the follow
+//! history, oldest first.
+//!
+//! Where the user was when quitting is also kept in a session file next to the project, even
+//! when the project isn't saved. It starts with the checksum of the ROM without the patches, the
+//! session is dropped if the ROM changed:
+//!
+//! ```text
+//! rom 9a3c51e07f2b64d8
+//! position 000150 000153
+//! follow 000100
+//! ```
+//!
+//! `position` gives the first address shown and the selected one.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::disassembler::{Architecture, ByteType, Disassembler};
+
+/// Where the user has been in the ROM, saved along with the annotations
+#[derive(Default)]
+pub struct Session {
+    pub follow_stack: Vec<usize>,
+    /// The first address shown and the selected one
+    pub position: Option<(usize, usize)>,
+}
+
+/// Returns where the session of the project at `project_path` is kept
+pub fn session_path(project_path: &Path) -> PathBuf {
+    let mut path = OsString::from(project_path);
+    path.push("-session");
+    PathBuf::from(path)
+}
+
+pub fn save_session(path: &Path, rom: &[u8], session: &Session) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "rom {:016x}", checksum(rom))?;
+    if let Some((base_address, selected_address)) = session.position {
+        writeln!(
+            file,
+            "position {:06x} {:06x}",
+            base_address, selected_address
+        )?;
+    }
+    for address in &session.follow_stack {
+        writeln!(file, "follow {:06x}", address)?;
+    }
+    file.flush()
+}
+
+/// Loads the session saved for `rom`, None if it was saved for another ROM
+pub fn load_session(path: &Path, rom: &[u8]) -> io::Result<Option<Session>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut session = Session::default();
+    let mut lines = file.lines();
+    let expected = format!("rom {:016x}", checksum(rom));
+    match lines.next().transpose()? {
+        Some(line) if line == expected => {}
+        _ => return Ok(None),
+    }
+    for (line_index, line) in lines.enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        line.split_once(' ')
+            .and_then(|(kind, arguments)| {
+                parse_session_line(kind, arguments, rom.len(), &mut session)
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid line {}: {}", line_index + 2, line),
+                )
+            })?;
+    }
+    Ok(Some(session))
+}
+
+pub fn save<Arch: Architecture>(
+    path: &Path,
+    disassembler: &Disassembler<Arch>,
+    session: &Session,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    let rom_length = disassembler.rom().len();
+    let mut start = 0;
+    while start < rom_length {
+        let byte_type = disassembler.byte_type(start);
+        let mut end = start + 1;
+        while end < rom_length && disassembler.byte_type(end) == byte_type {
+            end += 1;
+        }
+        if let Some(name) = type_name(byte_type) {
+            writeln!(file, "type {} {:06x} {:x}", name, start, end - start)?;
+        }
+        start = end;
+    }
+
+    let mut labels: Vec<_> = disassembler.labels().collect();
+    labels.sort_unstable();
+    for (address, name) in labels {
+        writeln!(file, "label {:06x} {}", address, name)?;
+    }
+
+    let mut comments: Vec<_> = disassembler.comments().collect();
+    comments.sort_unstable();
+    for (address, comment) in comments {
+        writeln!(file, "comment {:06x} {}", address, comment)?;
+    }
+
+    let mut bookmarks: Vec<_> = disassembler.bookmarks().collect();
+    bookmarks.sort_unstable();
+    for (name, address) in bookmarks {
+        writeln!(file, "bookmark {} {:06x}", name, address)?;
+    }
+
+    let mut banks: Vec<_> = disassembler.banks().collect();
+    banks.sort_unstable();
+    for (location, bank) in banks {
+        writeln!(file, "bank {:06x} {:x}", location, bank)?;
+    }
+
+    for (start, end, bank) in disassembler.bank_ranges() {
+        writeln!(
+            file,
+            "bank-range {:06x} {:x} {:x}",
+            start,
+            end - start,
+            bank
+        )?;
+    }
+
+    for (address, _) in disassembler.patches() {
+        writeln!(
+            file,
+            "patch {:06x} {:02x}",
+            address,
+            disassembler.rom()[address]
+        )?;
+    }
+
+    for address in &session.follow_stack {
+        writeln!(file, "follow {:06x}", address)?;
+    }
+
+    file.flush()
+}
+
+pub fn load<Arch: Architecture>(
+    path: &Path,
+    disassembler: &mut Disassembler<Arch>,
+) -> io::Result<Session> {
+    let file = BufReader::new(File::open(path)?);
+    let mut session = Session::default();
+    for (line_index, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        parse_line(&line, disassembler, &mut session).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid line {}: {}", line_index + 1, line),
+            )
+        })?;
+    }
+    Ok(session)
+}
+
+fn parse_line<Arch: Architecture>(
+    line: &str,
+    disassembler: &mut Disassembler<Arch>,
+    session: &mut Session,
+) -> Option<()> {
+    let (kind, arguments) = line.split_once(' ')?;
+    match kind {
+        "type" => {
+            let mut fields = arguments.split(' ');
+            let byte_type = parse_type_name(fields.next()?)?;
+            let start = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let length = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let end = start.checked_add(length)?;
+            if end > disassembler.rom().len() {
+                return None;
+            }
+            for address in start..end {
+                disassembler.set_byte_type(address, byte_type);
+            }
+        }
+        "label" => {
+            let (address, name) = arguments.split_once(' ')?;
+            let address = usize::from_str_radix(address, 16).ok()?;
+            if address >= disassembler.rom().len() {
+                return None;
+            }
+            disassembler.set_label(address, name.to_string());
+        }
+        "comment" => {
+            let (address, comment) = arguments.split_once(' ')?;
+            let address = usize::from_str_radix(address, 16).ok()?;
+            if address >= disassembler.rom().len() {
+                return None;
+            }
+            disassembler.set_comment(address, comment.to_string());
+        }
+        "bookmark" => {
+            let (name, address) = arguments.split_once(' ')?;
+            let mut chars = name.chars();
+            let name = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            let address = usize::from_str_radix(address, 16).ok()?;
+            if address >= disassembler.rom().len() {
+                return None;
+            }
+            disassembler.set_bookmark(name, address);
+        }
+        "bank" => {
+            let mut fields = arguments.split(' ');
+            let location = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let bank = usize::from_str_radix(fields.next()?, 16).ok()?;
+            if location >= disassembler.rom().len() {
+                return None;
+            }
+            disassembler.set_bank(location, bank);
+        }
+        "bank-range" => {
+            let mut fields = arguments.split(' ');
+            let start = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let length = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let bank = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let end = start.checked_add(length)?;
+            if length == 0 || end > disassembler.rom().len() {
+                return None;
+            }
+            disassembler.set_bank_range(start, end, bank);
+        }
+        "patch" => {
+            let (address, value) = arguments.split_once(' ')?;
+            let address = usize::from_str_radix(address, 16).ok()?;
+            let value = u8::from_str_radix(value, 16).ok()?;
+            if address >= disassembler.rom().len() {
+                return None;
+            }
+            disassembler.patch_byte(address, value);
+        }
+        "follow" => parse_session_line(kind, arguments, disassembler.rom().len(), session)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn parse_session_line(
+    kind: &str,
+    arguments: &str,
+    rom_length: usize,
+    session: &mut Session,
+) -> Option<()> {
+    match kind {
+        "follow" => {
+            let address = usize::from_str_radix(arguments, 16).ok()?;
+            if address >= rom_length {
+                return None;
+            }
+            session.follow_stack.push(address);
+        }
+        "position" => {
+            let (base_address, selected_address) = arguments.split_once(' ')?;
+            let base_address = usize::from_str_radix(base_address, 16).ok()?;
+            let selected_address = usize::from_str_radix(selected_address, 16).ok()?;
+            if base_address >= rom_length || selected_address >= rom_length {
+                return None;
+            }
+            session.position = Some((base_address, selected_address));
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// FNV-1a hash of the ROM, to tell whether a session was saved for it
+fn checksum(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn type_name(byte_type: ByteType) -> Option<&'static str> {
+    match byte_type {
+        ByteType::Unknown => None,
+        ByteType::Code => Some("code"),
+        ByteType::Data => Some("data"),
+        ByteType::Text => Some("text"),
+        ByteType::Pointer => Some("pointer"),
+    }
+}
+
+fn parse_type_name(name: &str) -> Option<ByteType> {
+    match name {
+        "code" => Some(ByteType::Code),
+        "data" => Some(ByteType::Data),
+        "text" => Some(ByteType::Text),
+        "pointer" => Some(ByteType::Pointer),
+        _ => None,
+    }
+}
@@ -0,0 +1,100 @@
+//! Symbol files in the `.sym` format read by no$gmb and BGB.
+//!
+//! Each line gives a label as `BB:AAAA Name`, with the bank and the address as the CPU sees it
+//! in hexadecimal. Comments start with `;`.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::disassembler::Disassembler;
+use crate::gb::{banked_address, GameBoy, BANK_SIZE};
+
+pub fn export(path: &Path, disassembler: &Disassembler<GameBoy>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    let mut labels: Vec<_> = disassembler.labels().collect();
+    labels.sort_unstable();
+    for (address, name) in labels {
+        let (bank, offset) = bank_and_offset(address);
+        writeln!(file, "{:02x}:{:04x} {}", bank, offset, name)?;
+    }
+
+    file.flush()
+}
+
+/// Adds the labels of a symbol file, returns how many were imported. Lines that can't be
+/// parsed and symbols outside of the ROM, such as RAM variables, are skipped. When an address
+/// is given several names, the last one wins.
+pub fn import(path: &Path, disassembler: &mut Disassembler<GameBoy>) -> io::Result<usize> {
+    let file = BufReader::new(File::open(path)?);
+    let mut count = 0;
+    for line in file.lines() {
+        let line = line?;
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if let Some((address, name)) = parse_line(line) {
+            if address < disassembler.rom().len() {
+                disassembler.set_label(address, name.to_string());
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Parses a `BB:AAAA Name` line into the ROM address and the name
+fn parse_line(line: &str) -> Option<(usize, &str)> {
+    let (location, name) = line.split_once(char::is_whitespace)?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let (bank, offset) = location.split_once(':')?;
+    let bank = usize::from_str_radix(bank, 16).ok()?;
+    let offset = u16::from_str_radix(offset, 16).ok()?;
+    Some((banked_address(bank, offset)?, name))
+}
+
+/// Converts a ROM address to the bank holding it and the address the CPU sees it at
+fn bank_and_offset(address: usize) -> (usize, usize) {
+    let bank = address / BANK_SIZE;
+    if bank == 0 {
+        (0, address)
+    } else {
+        (bank, BANK_SIZE + address % BANK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert_eq!(parse_line("00:0150 Start"), Some((0x150, "Start")));
+        assert_eq!(parse_line("02:4010 Banked"), Some((0x8010, "Banked")));
+        // Bank 0 above 0x4000, for ROMs without bank switching
+        assert_eq!(parse_line("00:4010 Flat"), Some((0x4010, "Flat")));
+        assert_eq!(parse_line("00:c000 wVariable"), None);
+        assert_eq!(parse_line("00:0150"), None);
+        assert_eq!(parse_line("0150 Start"), None);
+        assert_eq!(parse_line("ffffffffffffffff:4000 Overflow"), None);
+    }
+
+    #[test]
+    fn import_labels() {
+        let path = std::env::temp_dir().join(format!("retool-sym-{}.sym", std::process::id()));
+        std::fs::write(
+            &path,
+            "; comment\n00:0150 First\n00:0150 Second ; last wins\n00:c000 wRam\n01:4000 Past\n",
+        )
+        .unwrap();
+        let mut disassembler = Disassembler::<GameBoy>::new(vec![0; 0x200]);
+        let count = import(&path, &mut disassembler);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(count.unwrap(), 2);
+        assert_eq!(disassembler.label(0x150), Some("Second"));
+        assert_eq!(disassembler.labels().count(), 1);
+    }
+}